@@ -0,0 +1,648 @@
+/*
+ * A simple bus for the 6502: regions of RAM and ROM mapped into the 64KB address space.
+ *
+ * Nobody has to back the whole address space. Reading an address no region decodes returns
+ * the last value seen on the data bus, as happens on real boards where nobody drives the lines
+ * and their capacitance remembers the previous cycle (often the high byte of the operand).
+ * Writes to unmapped addresses are lost. Regions mapped later take precedence.
+ * Boards that decode everything have no use for open bus: unmapped accesses can be bus errors.
+ *
+ * Read only regions behave like a real ROM: the write cycle happens, but the value is lost.
+ * Firmware writing into its own ROM is almost always a bug, so the bus can also record the
+ * first offending write, or raise a bus fault which stops the processor.
+ * The bus knows nothing about the processor, so the PC must be added by who runs the show.
+ *
+ * Devices are attached to address ranges like regions. Clocking every device every cycle would be
+ * slow, so the bus keeps a schedule instead: each device tells when its next event is due (a timer
+ * underflow, a byte shifted out) and is caught up only then, or when the cpu touches its registers.
+ * Between events the bus clock is a counter and nothing more. The interrupt outputs are wired
+ * together to the cpu lines, and change only at events or register accesses: the clock tells the cpu
+ * when there is something to look at, an event or an access with something to say (a register, wait
+ * states, a fault). Other cycles it doesn't look at the bus at all.
+ *
+ * Slow regions (EEPROMs, I/O chips behind clock stretching logic) can ask for wait states: the
+ * bus adds them up access after access and the cpu stalls for as many cycles, like RDY held low.
+ * Devices doing DMA take the bus the same way: the whole transfer goes at once, a byte a cycle,
+ * and the cpu waits it out. DMA reaches memory only: device windows read as the data bus and
+ * drop writes. Writes into ROM are lost, as usual, and not reported: the cpu didn't do them.
+ *
+ * The bus also keeps a shadow map of the bytes that have ever been written (or loaded, which
+ * is the same thing), to catch reads and opcode fetches from uninitialized memory. Discarded
+ * reads are not checked: the cpu does plenty of them, e.g. on the stack during JSR.
+ */
+
+use cpu::{Memory, BusFault};
+use rng::Rng;
+use devices::{Device, Dma};
+
+const UNMAPPED: u8 = 0xFF;
+const DEVICE: u8 = 0x80;        // map entries from here on are devices
+const NEVER: u64 = !0;
+
+// what to do when the bus catches something fishy
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Check {
+    Ignore,         // like real hardware
+    Report,         // record the first violation, then go on
+    Stop,           // record the first violation and raise a bus fault
+}
+
+// power on content of RAM. Filling doesn't count as initialization
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Fill {
+    Value(u8),
+    Stripes(usize),     // alternating blocks of 0x00 and 0xFF of the given size, like many DRAMs
+    Random(u64),        // seed
+}
+
+struct Region {
+    start: u16,
+    m: Vec<u8>,
+    readonly: bool,
+}
+
+struct Slot {
+    start: u16,
+    dev: Box<dyn Device>,
+    synced: u64,        // bus cycle the device has been caught up to
+    due: u64,           // bus cycle of its next event
+}
+
+pub struct Bus {
+    regions: Vec<Region>,
+    devices: Vec<Slot>,
+    map: Vec<u8>,                           // address -> region index, DEVICE + device index, or UNMAPPED
+    init: Vec<bool>,                        // shadow map, true if ever written
+    wait: Vec<u8>,                          // wait states for each address
+    waits: u16,                             // wait states collected since the cpu last asked, DMA included
+    pub data_bus: u8,                       // last value seen on the data bus
+    fault: Option<(BusFault, u16)>,
+    pub unmapped_faults: bool,              // unmapped accesses are bus errors, no open bus
+    pub cycle: u64,                         // bus clock
+    next_due: u64,                          // first device event
+    touched: bool,                          // a device was looked at since the last tick, the lines may have moved
+    irq: bool,                              // wired-OR of the device outputs
+    nmi: bool,
+    pub rom_writes: Check,
+    pub rom_violation: Option<(u16, u8)>,   // first write into ROM: address, value
+    pub rom_violations: u64,                // total count
+    pub uninit_reads: Check,
+    pub uninit_violation: Option<(u16, bool)>,  // first read of uninitialized memory: address, true if opcode fetch
+    pub uninit_violations: u64,
+}
+
+impl Bus {
+    pub fn new() -> Bus {
+        Bus {
+            regions: Vec::new(),
+            devices: Vec::new(),
+            map: vec![UNMAPPED; 65536],
+            init: vec![false; 65536],
+            wait: vec![0u8; 65536],
+            waits: 0,
+            data_bus: 0x00,
+            fault: None,
+            unmapped_faults: false,
+            cycle: 0,
+            next_due: NEVER,
+            touched: false,
+            irq: false,
+            nmi: false,
+            rom_writes: Check::Ignore,
+            rom_violation: None,
+            rom_violations: 0,
+            uninit_reads: Check::Ignore,
+            uninit_violation: None,
+            uninit_violations: 0,
+        }
+    }
+
+    // map size bytes of RAM at address. wraps around at top of memory
+    pub fn map_ram(&mut self, address: u16, size: usize) -> Result<(), &'static str> {
+        self.map_region(address, vec![0u8; size], false)
+    }
+
+    // map a ROM with the given content at address. It counts as initialized, of course
+    pub fn map_rom(&mut self, address: u16, content: Vec<u8>) -> Result<(), &'static str> {
+        let len = content.len();
+        self.map_region(address, content, true)?;
+        for i in 0..len {
+            self.init[address.wrapping_add(i as u16) as usize] = true;
+        }
+        Ok(())
+    }
+
+    // fill all RAM regions. ROMs are left alone
+    pub fn fill(&mut self, fill: Fill) {
+        let mut rng = Rng::new(if let Fill::Random(seed) = fill { seed } else { 0 });
+        for r in self.regions.iter_mut().filter(|r| !r.readonly) {
+            for i in 0..r.m.len() {
+                let a = r.start.wrapping_add(i as u16) as usize;
+                r.m[i] = match fill {
+                    Fill::Value(v) => v,
+                    Fill::Stripes(size) => if (a / size).is_multiple_of(2) { 0x00 } else { 0xFF },
+                    Fill::Random(_) => rng.next_u8(),
+                };
+            }
+        }
+    }
+
+    // run f with the uninitialized memory checks off, for what happens before the program does,
+    // like the reset sequence reading the vector
+    pub fn unchecked<T, F: FnOnce(&mut Bus) -> T>(&mut self, f: F) -> T {
+        let check = self.uninit_reads;
+        self.uninit_reads = Check::Ignore;
+        let r = f(self);
+        self.uninit_reads = check;
+        r
+    }
+
+    // a writable region decodes the address
+    pub fn is_ram(&self, a: u16) -> bool {
+        match self.map[a as usize] {
+            UNMAPPED => false,
+            d if d >= DEVICE => false,
+            r => !self.regions[r as usize].readonly,
+        }
+    }
+
+    // every access to len bytes starting at address will cost cycles more
+    pub fn set_wait_states(&mut self, address: u16, len: usize, cycles: u8) {
+        for i in 0..len {
+            self.wait[address.wrapping_add(i as u16) as usize] = cycles;
+        }
+    }
+
+    fn map_region(&mut self, address: u16, m: Vec<u8>, readonly: bool) -> Result<(), &'static str> {
+        if m.is_empty() || m.len() > 65536 {
+            return Err("Region size must be between 1 byte and 64KB");
+        }
+        if self.regions.len() >= DEVICE as usize {
+            return Err("Too many regions!");
+        }
+        let index = self.regions.len() as u8;
+        for i in 0..m.len() {
+            self.map[address.wrapping_add(i as u16) as usize] = index;
+        }
+        self.regions.push(Region { start: address, m, readonly });
+        Ok(())
+    }
+
+    // attach a device with len registers at address. Its registers count as initialized
+    pub fn attach(&mut self, address: u16, len: usize, dev: Box<dyn Device>) -> Result<(), &'static str> {
+        if len == 0 || len > 65536 {
+            return Err("Device size must be between 1 byte and 64KB");
+        }
+        if self.devices.len() >= (UNMAPPED - DEVICE) as usize {
+            return Err("Too many devices!");
+        }
+        let index = DEVICE + self.devices.len() as u8;
+        for i in 0..len {
+            let a = address.wrapping_add(i as u16) as usize;
+            self.map[a] = index;
+            self.init[a] = true;
+        }
+        let cycle = self.cycle;
+        self.devices.push(Slot { start: address, dev, synced: cycle, due: NEVER });
+        let last = self.devices.len() - 1;
+        self.schedule(last, address);
+        Ok(())
+    }
+
+    // cycles to the next device event, if any
+    pub fn next_event(&self) -> Option<u64> {
+        if self.next_due == NEVER { None } else { Some(self.next_due.saturating_sub(self.cycle)) }
+    }
+
+    // catch up every device and look at them again. For the host side, after driving device pins
+    pub fn update(&mut self) {
+        for i in 0..self.devices.len() {
+            self.sync(i);
+            let start = self.devices[i].start;
+            self.schedule(i, start);
+        }
+    }
+
+    // catch up device i with the bus clock
+    fn sync(&mut self, i: usize) {
+        let s = &mut self.devices[i];
+        if self.cycle > s.synced {
+            s.dev.tick(self.cycle - s.synced);
+            s.synced = self.cycle;
+        }
+    }
+
+    // ask device i when it needs us next, then collect what changed. Faults are reported at
+    // address, the register accessed if any
+    fn schedule(&mut self, i: usize, address: u16) {
+        let dma = self.devices[i].dev.dma();
+        if let Some(dma) = dma {
+            self.dma(i, dma);
+        }
+        let cycle = self.cycle;
+        let fault = {
+            let s = &mut self.devices[i];
+            s.due = s.dev.next_event().map(|n| cycle + n.max(1)).unwrap_or(NEVER);
+            s.dev.fault()
+        };
+        if let Some(f) = fault {
+            self.raise(f, address);
+        }
+        self.next_due = self.devices.iter().map(|s| s.due).min().unwrap_or(NEVER);
+        self.touched = true;
+        self.irq = self.devices.iter().any(|s| s.dev.irq());
+        self.nmi = self.devices.iter().any(|s| s.dev.nmi());
+    }
+
+    // a transfer for device i, with the cycles it steals from the cpu
+    fn dma(&mut self, i: usize, dma: Dma) {
+        let len = match dma {
+            Dma::Read(address, len) => {
+                let data = (0..len).map(|n| {
+                    let a = address.wrapping_add(n as u16);
+                    match self.map[a as usize] {
+                        r if r < DEVICE => {
+                            let r = &self.regions[r as usize];
+                            r.m[a.wrapping_sub(r.start) as usize]
+                        }
+                        _ => self.data_bus,
+                    }
+                }).collect();
+                self.devices[i].dev.dma_read(data);
+                len
+            }
+            Dma::Write(address, data) => {
+                for (n, &v) in data.iter().enumerate() {
+                    let a = address.wrapping_add(n as u16);
+                    let r = self.map[a as usize];
+                    if r < DEVICE {
+                        let r = &mut self.regions[r as usize];
+                        if !r.readonly {
+                            r.m[a.wrapping_sub(r.start) as usize] = v;
+                            self.init[a as usize] = true;
+                        }
+                    }
+                }
+                data.len()
+            }
+        };
+        self.waits = self.waits.saturating_add(len.min(0xFFFF) as u16);
+    }
+
+    // access to a device register: catch it up, do the job, reschedule
+    fn device<T, F>(&mut self, d: u8, a: u16, f: F) -> T where F: FnOnce(&mut dyn Device, u16) -> T {
+        let i = (d - DEVICE) as usize;
+        self.sync(i);
+        let v = {
+            let s = &mut self.devices[i];
+            f(&mut *s.dev, a.wrapping_sub(s.start))
+        };
+        self.schedule(i, a);
+        v
+    }
+
+    // value at address, or the data bus if unmapped. Devices are read, side effects and all
+    fn get(&mut self, a: u16) -> u8 {
+        match self.map[a as usize] {
+            UNMAPPED => self.data_bus,
+            d if d >= DEVICE => self.device(d, a, |dev, reg| dev.read(reg)),
+            r => {
+                let r = &self.regions[r as usize];
+                r.m[a.wrapping_sub(r.start) as usize]
+            }
+        }
+    }
+
+    fn check_init(&mut self, a: u16, fetch: bool) {
+        if !self.init[a as usize] && self.map[a as usize] != UNMAPPED && self.uninit_reads != Check::Ignore {
+            if self.uninit_violation.is_none() {
+                self.uninit_violation = Some((a, fetch));
+            }
+            self.uninit_violations += 1;
+            if self.uninit_reads == Check::Stop {
+                self.raise(BusFault::Uninitialized, a);
+            }
+        }
+    }
+
+    fn check_mapped(&mut self, a: u16) {
+        if self.unmapped_faults && self.map[a as usize] == UNMAPPED {
+            self.raise(BusFault::Unmapped, a);
+        }
+    }
+
+    // the first fault wins
+    fn raise(&mut self, fault: BusFault, a: u16) {
+        if self.fault.is_none() {
+            self.fault = Some((fault, a));
+        }
+    }
+}
+
+impl Memory for Bus {
+    fn read(&mut self, a: u16) -> u8 {
+        self.check_mapped(a);
+        self.check_init(a, false);
+        self.waits = self.waits.saturating_add(self.wait[a as usize] as u16);
+        self.data_bus = self.get(a);
+        self.data_bus
+    }
+    fn write(&mut self, a: u16, v: u8) {
+        self.check_mapped(a);
+        self.waits = self.waits.saturating_add(self.wait[a as usize] as u16);
+        self.data_bus = v;
+        match self.map[a as usize] {
+            UNMAPPED => {},
+            d if d >= DEVICE => self.device(d, a, |dev, reg| dev.write(reg, v)),
+            r => {
+                let r = &mut self.regions[r as usize];
+                if !r.readonly {
+                    r.m[a.wrapping_sub(r.start) as usize] = v;
+                    self.init[a as usize] = true;
+                } else if self.rom_writes != Check::Ignore {
+                    if self.rom_violation.is_none() {
+                        self.rom_violation = Some((a, v));
+                    }
+                    self.rom_violations += 1;
+                    if self.rom_writes == Check::Stop {
+                        self.raise(BusFault::Protection, a);
+                    }
+                }
+            }
+        }
+    }
+    fn fetch(&mut self, a: u16) -> u8 {
+        self.check_mapped(a);
+        self.check_init(a, true);
+        self.waits = self.waits.saturating_add(self.wait[a as usize] as u16);
+        self.data_bus = self.get(a);
+        self.data_bus
+    }
+    fn read_discard(&mut self, a: u16) -> u8 {
+        self.check_mapped(a);
+        self.waits = self.waits.saturating_add(self.wait[a as usize] as u16);
+        self.data_bus = self.get(a);
+        self.data_bus
+    }
+    fn wait_states(&mut self) -> u16 {
+        let w = self.waits;
+        self.waits = 0;
+        w
+    }
+    fn fault(&mut self) -> Option<(BusFault, u16)> {
+        self.fault.take()
+    }
+    fn tick(&mut self) -> bool {
+        self.cycle += 1;
+        if self.cycle >= self.next_due {
+            for i in 0..self.devices.len() {
+                if self.devices[i].due <= self.cycle {
+                    self.sync(i);
+                    let start = self.devices[i].start;
+                    self.schedule(i, start);
+                }
+            }
+        }
+        let look = self.touched || self.waits > 0 || self.fault.is_some();
+        self.touched = false;
+        look
+    }
+    fn irq(&mut self) -> bool {
+        self.irq
+    }
+    fn nmi(&mut self) -> bool {
+        self.nmi
+    }
+    fn peek(&mut self, a: u16) -> u8 {
+        match self.map[a as usize] {
+            UNMAPPED => self.data_bus,
+            d if d >= DEVICE => self.device(d, a, |dev, reg| dev.peek(reg)),
+            r => {
+                let r = &self.regions[r as usize];
+                r.m[a.wrapping_sub(r.start) as usize]
+            }
+        }
+    }
+    // patches ROMs too, it's a debugger
+    fn poke(&mut self, a: u16, v: u8) {
+        match self.map[a as usize] {
+            UNMAPPED => {},
+            d if d >= DEVICE => self.device(d, a, |dev, reg| dev.poke(reg, v)),
+            r => {
+                let r = &mut self.regions[r as usize];
+                r.m[a.wrapping_sub(r.start) as usize] = v;
+                self.init[a as usize] = true;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bus::{Bus, Check};
+    use cpu::{BusFault, Memory, P65};
+    use devices::debug::Debug;
+    use devices::disk::Disk;
+    use devices::timer::Timer;
+
+    #[test]
+    fn discarded_reads_are_checked_too() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.unmapped_faults = true;
+        bus.read_discard(0x0800);
+        assert_eq!(bus.fault(), None);
+        bus.read_discard(0x2000);
+        assert_eq!(bus.fault(), Some((BusFault::Unmapped, 0x2000)));
+    }
+
+    #[test]
+    fn unmapped_read_stops_the_cpu() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.unmapped_faults = true;
+        bus.write(0x200, 0xAD); bus.write(0x201, 0x00); bus.write(0x202, 0x20);   // LDA $2000
+        let mut cpu = P65::new();
+        cpu.reset(&mut bus);        // no vectors up there, that's not the program's fault
+        cpu.jump(&mut bus, 0x200);
+        let stop = cpu.step(&mut bus, 1).unwrap_err();
+        assert_eq!(stop.fault, BusFault::Unmapped);
+        assert_eq!(stop.address, 0x2000);
+        assert_eq!(stop.pc, 0x200);
+    }
+
+    #[test]
+    fn device_faults_at_the_register() {
+        let mut bus = Bus::new();
+        bus.attach(0xF000, 7, Box::new(Debug::new(Box::new(Vec::new())))).unwrap();
+        bus.write(0xF006, 0x42);
+        assert_eq!(bus.fault(), Some((BusFault::Assert(0x42), 0xF006)));
+    }
+
+    #[test]
+    fn run_returns_the_stop() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.attach(0xF000, 7, Box::new(Debug::new(Box::new(Vec::new())))).unwrap();
+        bus.write(0x200, 0xEA);                                                    // NOP
+        bus.write(0x201, 0xA9); bus.write(0x202, 0x42);                            // LDA #$42
+        bus.write(0x203, 0x8D); bus.write(0x204, 0x06); bus.write(0x205, 0xF0);    // STA $F006
+        bus.write(0x206, 0x4C); bus.write(0x207, 0x06); bus.write(0x208, 0x02);    // JMP $0206
+        let mut cpu = P65::new();
+        cpu.jump(&mut bus, 0x200);
+        let stop = cpu.run(&mut bus, 1000).unwrap_err();
+        assert_eq!(stop.fault, BusFault::Assert(0x42));
+        assert_eq!(stop.address, 0xF006);
+        assert_eq!(stop.pc, 0x203);
+        assert!(stop.cycle < 1000);
+    }
+
+    #[test]
+    fn the_clock_says_when_to_look() {
+        let mut bus = Bus::new();
+        bus.attach(0xF000, 6, Box::new(Timer::running(10, false))).unwrap();
+        assert!(bus.tick());        // attached: the lines may have moved
+        for _ in 2..10 {
+            assert!(!bus.tick());
+        }
+        assert!(!bus.irq());
+        assert!(bus.tick());
+        assert!(bus.irq());
+        assert!(!bus.tick());
+        bus.write(0xF005, 0);       // acknowledge
+        assert!(bus.tick());
+        assert!(!bus.irq());
+    }
+
+    #[test]
+    fn devices_are_caught_up_on_access() {
+        let mut bus = Bus::new();
+        bus.attach(0xF000, 6, Box::new(Timer::running(1000, false))).unwrap();
+        for _ in 0..300 {
+            bus.tick();
+        }
+        assert_eq!(bus.next_event(), Some(700));
+        assert_eq!(bus.read(0xF000) as u16 | (bus.read(0xF001) as u16) << 8, 700);
+    }
+
+    #[test]
+    fn the_cpu_takes_the_interrupt_at_the_event() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.map_rom(0xFFFE, vec![0x00, 0x03]).unwrap();
+        bus.write(0x200, 0x58);                                     // CLI
+        bus.write(0x201, 0x4C); bus.write(0x202, 0x01); bus.write(0x203, 0x02);   // JMP $0201
+        bus.attach(0xF000, 6, Box::new(Timer::running(100, false))).unwrap();
+        let mut cpu = P65::new();
+        cpu.jump(&mut bus, 0x200);
+        cpu.run(&mut bus, 95).unwrap();
+        assert!(cpu.op_pc() < 0x300);
+        cpu.run(&mut bus, 20).unwrap();
+        assert_eq!(cpu.op_pc(), 0x300);
+    }
+
+    #[test]
+    fn the_stack_starts_at_fd() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.map_rom(0xFFFC, vec![0x00, 0x02]).unwrap();
+        bus.write(0x200, 0xBA);                         // TSX
+        bus.write(0x201, 0x86); bus.write(0x202, 0x10); // STX $10
+        let mut cpu = P65::new();
+        cpu.jump(&mut bus, 0x200);
+        cpu.step(&mut bus, 2).unwrap();
+        assert_eq!(bus.peek(0x10), 0xFD);
+        for _ in 0..3 {
+            bus.poke(0x10, 0);
+            cpu.reset(&mut bus);
+            cpu.step(&mut bus, 2).unwrap();
+            assert_eq!(bus.peek(0x10), 0xFD);
+        }
+        cpu.reset(&mut bus);
+        cpu.set_registers(0, 0, 0, 0x37, 0);
+        cpu.step(&mut bus, 2).unwrap();
+        assert_eq!(bus.peek(0x10), 0x37);
+    }
+
+    #[test]
+    fn reset_is_not_the_program() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x10000).unwrap();
+        bus.uninit_reads = Check::Stop;
+        bus.poke(0x200, 0xA5); bus.poke(0x201, 0x10);   // LDA $10
+        bus.poke(0xFFFC, 0x00); bus.poke(0xFFFD, 0x02);
+        let mut cpu = P65::new();
+        bus.unchecked(|bus| cpu.reset(bus));
+        assert_eq!(bus.uninit_violation, None);
+        assert_eq!(bus.uninit_reads, Check::Stop);
+        assert_eq!(cpu.step(&mut bus, 1).unwrap_err().fault, BusFault::Uninitialized);
+        assert_eq!(bus.uninit_violation, Some((0x10, false)));
+        assert_eq!(bus.uninit_violations, 1);
+    }
+
+    #[test]
+    fn wait_states_stall_the_cpu() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.write(0x200, 0xEA);     // NOP NOP
+        bus.write(0x201, 0xEA);
+        bus.set_wait_states(0x201, 1, 3);
+        let mut cpu = P65::new();
+        cpu.jump(&mut bus, 0x200);
+        let start = cpu.cycle;
+        cpu.step(&mut bus, 2).unwrap();
+        assert_eq!(cpu.cycle - start, 4 + 2 * 3);     // 201 is read by the first NOP too, and thrown away
+    }
+
+    #[test]
+    fn only_ram_is_ram() {
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.map_rom(0xE000, vec![0x55; 16]).unwrap();
+        bus.attach(0xF000, 7, Box::new(Debug::new(Box::new(Vec::new())))).unwrap();
+        assert!(bus.is_ram(0x0000) && bus.is_ram(0x0FFF));
+        assert!(!bus.is_ram(0x1000));
+        assert!(!bus.is_ram(0xE000));
+        assert!(!bus.is_ram(0xF000));
+    }
+
+    #[test]
+    fn report_records_stop_faults() {
+        let mut bus = Bus::new();
+        bus.map_rom(0xE000, vec![0x55; 16]).unwrap();
+        bus.rom_writes = Check::Report;
+        bus.write(0xE001, 1);
+        bus.write(0xE002, 2);
+        assert_eq!(bus.fault(), None);
+        assert_eq!(bus.rom_violation, Some((0xE001, 1)));
+        assert_eq!(bus.rom_violations, 2);
+        bus.rom_writes = Check::Stop;
+        bus.write(0xE003, 3);
+        assert_eq!(bus.fault(), Some((BusFault::Protection, 0xE003)));
+        assert_eq!(bus.rom_violation, Some((0xE001, 1)));
+        assert_eq!(bus.read(0xE003), 0x55);
+    }
+
+    #[test]
+    fn dma_stretches_a_stall() {
+        let path = ::std::env::temp_dir().join(format!("hell65-{}-dma-stall.img", ::std::process::id()));
+        ::std::fs::write(&path, vec![0x55; 512]).unwrap();
+        let mut bus = Bus::new();
+        bus.map_ram(0x0000, 0x1000).unwrap();
+        bus.attach(0xF000, 8, Box::new(Disk::new(::std::fs::File::open(&path).unwrap(), true, 10).unwrap())).unwrap();
+        bus.write(0x200, 0xA9);     // LDA #$42, NOP
+        bus.write(0x201, 0x42);
+        bus.write(0x202, 0xEA);
+        bus.set_wait_states(0x201, 1, 20);
+        bus.write(0xF003, 0x03);
+        bus.write(0xF004, 1);       // the sector comes in the midst of the operand wait states
+        let mut cpu = P65::new();
+        cpu.jump(&mut bus, 0x200);
+        cpu.run(&mut bus, 1 + 20 + 512).unwrap();
+        assert_eq!(cpu.pc, 0x202);  // not a cycle in between
+        assert_eq!(bus.peek(0x300), 0x55);
+        cpu.run(&mut bus, 1).unwrap();
+        assert_eq!(cpu.pc, 0x203);
+        ::std::fs::remove_file(path).unwrap();
+    }
+}
@@ -3,8 +3,62 @@ use std::fmt;
 
 // simple trait for memory operations
 pub trait Memory {
-    fn read(&mut self, a: usize) -> u8;
-    fn write(&mut self, a: usize, v: u8); 
+    fn read(&mut self, a: u16) -> u8;
+    fn write(&mut self, a: u16, v: u8); 
+    // opcode fetch (SYNC high). For the bus it is a read like any other, override to tell them apart
+    fn fetch(&mut self, a: u16) -> u8 { self.read(a) }
+    // a read whose value is thrown away by the cpu. It still happens on the bus, side effects included
+    fn read_discard(&mut self, a: u16) -> u8 { self.read(a) }
+    // wait states requested by the accesses since the last call. The cpu stalls for as many cycles, like RDY held low.
+    // DMA steals cycles the same way
+    fn wait_states(&mut self) -> u16 { 0 }
+    // debugger access, never used by the cpu: no side effects on devices, no wait states, no checks.
+    // Whoever has read sensitive locations must override them
+    fn peek(&mut self, a: u16) -> u8 { self.read(a) }
+    fn poke(&mut self, a: u16, v: u8) { self.write(a, v) }
+    // a fault raised by the accesses since the last call, if any, with the address. The cpu stops and reports it
+    fn fault(&mut self) -> Option<(BusFault, u16)> { None }
+    // bus clock, once per cpu cycle (stalls included), after the access. Devices on the bus live here.
+    // True if the cpu must look at the bus: an event came due, or an access moved the interrupt lines,
+    // asked for wait states or faulted. Lines, wait states and faults are looked at only then
+    fn tick(&mut self) -> bool { true }
+    // /IRQ and /NMI as driven by the devices on the bus, true if asserted
+    fn irq(&mut self) -> bool { false }
+    fn nmi(&mut self) -> bool { false }
+}
+
+// what can go wrong on the bus
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum BusFault {
+    Unmapped,                   // nobody answers at the address
+    Protection,                 // e.g. a write into ROM
+    Uninitialized,              // read of memory never written
+    Device(&'static str),       // a device has something to say
+    Exit(u8),                   // the program asks to end the run, with a status
+    Assert(u8),                 // the program found itself wrong. Which assertion
+}
+
+// why run or step stopped early: the cycle with the faulty access is complete, run again to go on
+#[derive(Clone, Copy, Debug)]
+pub struct Stop {
+    pub fault: BusFault,
+    pub address: u16,
+    pub pc: u16,                // of the instruction doing the access
+    pub cycle: u64,
+}
+
+impl fmt::Display for Stop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let what = match self.fault {
+            BusFault::Exit(code) => return write!(f, "Exit {}, PC {:04x}, cycle {}", code, self.pc, self.cycle),
+            BusFault::Assert(n) => return write!(f, "Assertion {:02x} failed, PC {:04x}, cycle {}", n, self.pc, self.cycle),
+            BusFault::Unmapped => "unmapped access".to_string(),
+            BusFault::Protection => "protection fault".to_string(),
+            BusFault::Uninitialized => "uninitialized memory".to_string(),
+            BusFault::Device(e) => format!("device error ({})", e),
+        };
+        write!(f, "Bus error: {} at {:04x}, PC {:04x}, cycle {}", what, self.address, self.pc, self.cycle)
+    }
 }
 
 // 6502 flags
@@ -78,8 +132,11 @@ pub struct P65 {
     al: u8,
     nmi: bool, nmi_cycle: u64, nmi_triggered: bool,
     irq: bool, irq_cycle: u64, irq_triggered: bool,
+    nmi_pin: bool, irq_pin: bool,       // lines driven from outside, ORed with the bus ones
+    bus_nmi: bool, bus_irq: bool,       // as the bus drove them when last looked at
     reset_triggered: bool,
     current_op_pc: u16,
+    stall: u16,         // cycles left with RDY low
 }
 
 type AddrModeF<M: Memory> = fn(&mut P65, &mut M,  fn(&mut P65));
@@ -99,7 +156,7 @@ impl P65 {
             x:  0, 
             y:  0, 
             p:  P65Flags {n: false, v: false, bit5: true, b: true, d: false, i: true, z: true, c: false,},
-            s:  0xfd, 
+            s:  0xfd,       // where reset leaves it, also for a jump without reset
             pc: 0, 
             cycle: 0, 
             ts: 0, 
@@ -110,8 +167,11 @@ impl P65 {
             al: 0,
             nmi_cycle: 0, nmi: false, nmi_triggered: false,
             irq_cycle: 0, irq: false, irq_triggered: false,
+            nmi_pin: false, irq_pin: false,
+            bus_nmi: false, bus_irq: false,
             reset_triggered: false,
             current_op_pc: 0,
+            stall: 0,
         }
     }
 
@@ -126,7 +186,7 @@ impl P65 {
     }
 
     fn fetch_op<M: Memory>(&mut self, mem: &mut M) -> u8 {
-        self.op = mem.read(self.pc as usize);
+        self.op = mem.fetch(self.pc);
         self.current_op_pc = self.pc;
         self.inc_pc();
         self.ts = 0;    // will be incremented to 1 by tick
@@ -323,110 +383,110 @@ impl P65 {
 
     fn a1_ac<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { mem.read(self.pc as usize); },      // discard read
+            1 => { mem.read_discard(self.pc); },      // discard read
             2 => { self.v1 = self.a; opfun(self); self.a = self.v1; self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a1_imp<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { mem.read(self.pc as usize); },      // discard read
+            1 => { mem.read_discard(self.pc); },      // discard read
             2 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_ix<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al = mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize);  self.v1 = self.al.wrapping_add(self.x); },    // discd read
-            3 => { self.al = mem.read(self.v1 as usize);  self.v1 = self.v1.wrapping_add(1); },
-            4 => { self.ah = mem.read(self.v1 as usize); },
-            5 => { self.v1 = mem.read(self.ah_al() as usize); },
+            1 => { self.al = mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16);  self.v1 = self.al.wrapping_add(self.x); },    // discd read
+            3 => { self.al = mem.read(self.v1 as u16);  self.v1 = self.v1.wrapping_add(1); },
+            4 => { self.ah = mem.read(self.v1 as u16); },
+            5 => { self.v1 = mem.read(self.ah_al()); },
             6 => { opfun(self); self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a2_imm<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.v1 =  mem.read(self.pc as usize); self.inc_pc(); },
+            1 => { self.v1 =  mem.read(self.pc); self.inc_pc(); },
             2 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_zp<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.v1 =  mem.read(self.al as usize); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.v1 =  mem.read(self.al as u16); },
             3 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_abs<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize); self.inc_pc(); },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc); self.inc_pc(); },
+            3 => { self.v1 =  mem.read(self.ah_al()); },
             4 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_iy<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.v1 =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.al =  mem.read(self.v1 as usize);  self.v1 = self.v1.wrapping_add(1); },
-            3 => { self.ah =  mem.read(self.v1 as usize);  
+            1 => { self.v1 =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.al =  mem.read(self.v1 as u16);  self.v1 = self.v1.wrapping_add(1); },
+            3 => { self.ah =  mem.read(self.v1 as u16);  
                                 self.v2 =  ((self.al as u32 + self.y as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.y); },
-            4 => { self.v1 =  mem.read(self.ah_al() as usize); 
+            4 => { self.v1 =  if self.v2 == 0 { mem.read(self.ah_al()) } else { mem.read_discard(self.ah_al()) };   // wrong page if v2 != 0
                                 self.ah = self.ah.wrapping_add(self.v2); 
                                 if self.v2 ==  (0) { self.ts_inc(); }; },
-            5 => { self.v1 =  mem.read(self.ah_al() as usize);  },
+            5 => { self.v1 =  mem.read(self.ah_al());  },
             6 => { opfun(self); self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a2_zpx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize); self.al = self.al.wrapping_add(self.x); },  //discard read
-            3 => { self.v1 =  mem.read(self.al as usize); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16); self.al = self.al.wrapping_add(self.x); },  //discard read
+            3 => { self.v1 =  mem.read(self.al as u16); },
             4 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_zpy<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize); self.al = self.al.wrapping_add(self.y); },  //discard read
-            3 => { self.v1 =  mem.read(self.al as usize); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16); self.al = self.al.wrapping_add(self.y); },  //discard read
+            3 => { self.v1 =  mem.read(self.al as u16); },
             4 => { opfun(self); self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a2_ay<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize);  self.inc_pc(); 
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc);  self.inc_pc(); 
                                 self.v2 =  ((self.al as u16 + self.y as u16) >> 8) as u8;  // here v2 is max 1
                                 self.al = self.al.wrapping_add(self.y); },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize); 
+            3 => { self.v1 =  if self.v2 == 0 { mem.read(self.ah_al()) } else { mem.read_discard(self.ah_al()) };   // wrong page if v2 != 0
                                 self.ah = self.ah.wrapping_add(self.v2); 
                                 if self.v2 ==  (0) { self.ts_inc(); }; },
-            4 => { self.v1 =  mem.read(self.ah_al() as usize);  },
+            4 => { self.v1 =  mem.read(self.ah_al());  },
             5 => { opfun(self); self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a2_ax<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize);  self.inc_pc();
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc);  self.inc_pc();
                                 self.v2 =  ((self.al as u32 + self.x as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.x); },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize); 
+            3 => { self.v1 =  if self.v2 == 0 { mem.read(self.ah_al()) } else { mem.read_discard(self.ah_al()) };   // wrong page if v2 != 0
                                 self.ah = self.ah.wrapping_add(self.v2); 
                                 if self.v2 == 0 { self.ts_inc(); }; },
-            4 => { self.v1 =  mem.read(self.ah_al() as usize);  },
+            4 => { self.v1 =  mem.read(self.ah_al());  },
             5 => { opfun(self); self.fetch_op(mem); },
             _ => {},
         }
@@ -434,8 +494,8 @@ impl P65 {
     
     fn a3_zp<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { opfun(self); mem.write(self.al as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { opfun(self); mem.write(self.al as u16, self.v1); },
             3 => { self.fetch_op(mem); }
             _ => {},
         }
@@ -443,112 +503,112 @@ impl P65 {
 
     fn a3_abs<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize); self.inc_pc(); },
-            3 => { opfun(self); mem.write(self.ah_al() as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc); self.inc_pc(); },
+            3 => { opfun(self); mem.write(self.ah_al(), self.v1); },
             4 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a3_ix<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { mem.read(self.al as usize);             self.v1 = self.al.wrapping_add(self.x); },     // discard read
-            3 => { self.al =  mem.read(self.v1 as usize);  self.v1 = self.v1.wrapping_add(1); },
-            4 => { self.ah =  mem.read(self.v1 as usize); },
-            5 => { opfun(self); mem.write(self.ah_al() as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16);             self.v1 = self.al.wrapping_add(self.x); },     // discard read
+            3 => { self.al =  mem.read(self.v1 as u16);  self.v1 = self.v1.wrapping_add(1); },
+            4 => { self.ah =  mem.read(self.v1 as u16); },
+            5 => { opfun(self); mem.write(self.ah_al(), self.v1); },
             6 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a3_ax<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize);  self.inc_pc();
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc);  self.inc_pc();
                                 self.v2 =  ((self.al as u32 + self.x as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.x); },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize); 
+            3 => { mem.read_discard(self.ah_al());          // discard read, maybe from the wrong page
                                 self.ah = self.ah.wrapping_add(self.v2); },
-            4 => { opfun(self); mem.write(self.ah_al() as usize, self.v1);  },
+            4 => { opfun(self); mem.write(self.ah_al(), self.v1);  },
             5 => { self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a3_ay<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize);  self.inc_pc();
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc);  self.inc_pc();
                                 self.v2 =  ((self.al as u32 + self.y as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.y); },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize); 
+            3 => { mem.read_discard(self.ah_al());          // discard read, maybe from the wrong page
                                 self.ah = self.ah.wrapping_add(self.v2); },
-            4 => { opfun(self); mem.write(self.ah_al() as usize, self.v1);  },
+            4 => { opfun(self); mem.write(self.ah_al(), self.v1);  },
             5 => { self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a3_zpx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize);       self.al = self.al.wrapping_add(self.x);          },  // discard  
-            3 => { opfun(self); mem.write(self.al as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16);       self.al = self.al.wrapping_add(self.x);          },  // discard  
+            3 => { opfun(self); mem.write(self.al as u16, self.v1); },
             4 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a3_zpy<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize);       self.al = self.al.wrapping_add(self.y);          },  // discard  
-            3 => { opfun(self); mem.write(self.al as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16);       self.al = self.al.wrapping_add(self.y);          },  // discard  
+            3 => { opfun(self); mem.write(self.al as u16, self.v1); },
             4 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a3_iy<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.v1 =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.al =  mem.read(self.v1 as usize);  self.v1 = self.v1.wrapping_add(1); },
-            3 => { self.ah =  mem.read(self.v1 as usize);  
+            1 => { self.v1 =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.al =  mem.read(self.v1 as u16);  self.v1 = self.v1.wrapping_add(1); },
+            3 => { self.ah =  mem.read(self.v1 as u16);  
                                 self.v2 =  ((self.al as u32 + self.y as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.y); },
-            4 => { mem.read(self.ah_al() as usize);          self.ah = self.ah.wrapping_add(self.v2); },
-            5 => { opfun(self); mem.write(self.ah_al() as usize, self.v1);  },
+            4 => { mem.read_discard(self.ah_al());          self.ah = self.ah.wrapping_add(self.v2); },
+            5 => { opfun(self); mem.write(self.ah_al(), self.v1);  },
             6 => { self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a4_zp<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.v1 =  mem.read(self.al as usize);                },
-            3 => { mem.write(self.al as usize, self.v1); },                          // wasted write
-            4 => { opfun(self); mem.write(self.al as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.v1 =  mem.read(self.al as u16);                },
+            3 => { mem.write(self.al as u16, self.v1); },                          // wasted write
+            4 => { opfun(self); mem.write(self.al as u16, self.v1); },
             5 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a4_zpx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read(self.al as usize); self.al = self.al.wrapping_add(self.x); },                 //discard read
-            3 => { self.v1 =  mem.read(self.al as usize); },
-            4 => { mem.write(self.al as usize, self.v1); },                          // wasted write
-            5 => { opfun(self); mem.write(self.al as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(self.al as u16); self.al = self.al.wrapping_add(self.x); },                 //discard read
+            3 => { self.v1 =  mem.read(self.al as u16); },
+            4 => { mem.write(self.al as u16, self.v1); },                          // wasted write
+            5 => { opfun(self); mem.write(self.al as u16, self.v1); },
             6 => { self.fetch_op(mem); }
             _ => {},
         }
     }
     fn a4_ax<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize);  self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize);  self.inc_pc();
+            1 => { self.al =  mem.read(self.pc);  self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc);  self.inc_pc();
                                 self.v2 =  ((self.al as u32 + self.x as u32) >> 8) as u8;
                                 self.al = self.al.wrapping_add(self.x); },
-            3 => { mem.read(self.ah_al() as usize); self.ah = self.ah.wrapping_add(self.v2); },        // discard read
-            4 => { self.v1 = mem.read(self.ah_al() as usize); },
-            5 => { mem.write(self.ah_al() as usize, self.v1);  },               // wasted write
-            6 => { opfun(self); mem.write(self.ah_al() as usize, self.v1);  },
+            3 => { mem.read_discard(self.ah_al()); self.ah = self.ah.wrapping_add(self.v2); },        // discard read
+            4 => { self.v1 = mem.read(self.ah_al()); },
+            5 => { mem.write(self.ah_al(), self.v1);  },               // wasted write
+            6 => { opfun(self); mem.write(self.ah_al(), self.v1);  },
             7 => { self.fetch_op(mem); },
             _ => {},
         }
@@ -556,11 +616,11 @@ impl P65 {
 
     fn a4_abs<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc();   },
-            2 => { self.ah =  mem.read(self.pc as usize); self.inc_pc();   },
-            3 => { self.v1 =  mem.read(self.ah_al() as usize);             },
-            4 => { mem.write(self.ah_al() as usize, self.v1);              },    // wasted write
-            5 => { opfun(self); mem.write(self.ah_al() as usize, self.v1); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc();   },
+            2 => { self.ah =  mem.read(self.pc); self.inc_pc();   },
+            3 => { self.v1 =  mem.read(self.ah_al());             },
+            4 => { mem.write(self.ah_al(), self.v1);              },    // wasted write
+            5 => { opfun(self); mem.write(self.ah_al(), self.v1); },
             6 => { self.fetch_op(mem); }
             _ => {},
         }
@@ -568,11 +628,11 @@ impl P65 {
     
     fn jsr_abs<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { mem.read((self.s as usize) + 0x100);  },        // discard read. s tack pointer is always 1xx
-            3 => { mem.write((self.s as usize) + 0x100, (self.pc >> 8) as u8);  self.dec_sp();  },
-            4 => { mem.write((self.s as usize) + 0x100, (self.pc & 0xFF) as u8); self.dec_sp(); },    // check with a real 6502
-            5 => { self.ah =  mem.read(self.pc as usize);  },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { mem.read_discard(0x100 | self.s as u16);  },        // discard read. s tack pointer is always 1xx
+            3 => { mem.write(0x100 | self.s as u16, (self.pc >> 8) as u8);  self.dec_sp();  },
+            4 => { mem.write(0x100 | self.s as u16, (self.pc & 0xFF) as u8); self.dec_sp(); },    // check with a real 6502
+            5 => { self.ah =  mem.read(self.pc);  },
             6 => { self.pc =  self.ah_al();      self.fetch_op(mem); },   // load PC and fetch. only one mem read       
             _ => {},
         }
@@ -591,25 +651,25 @@ impl P65 {
     // CHECK/FIXME . we drop the triggers at T6. A fast bouncing NMI or IRQ could be retriggered early.. What a real CPU would do?
     fn brk_imp<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { mem.read(self.pc as usize); self.inc_pc(); self.p.b = true; },  // discard read. note that ONLY the real BRK will be in T1, IRQ/NMI START FROM T2
+            1 => { mem.read_discard(self.pc); self.inc_pc(); self.p.b = true; },  // discard read. note that ONLY the real BRK will be in T1, IRQ/NMI START FROM T2
             2 => {       // ENTRY POINT for IRQs & NMIs. b MUST be false now , except for note bug
                 
                 if self.reset_triggered {   // this hack is from the cpu
-                    mem.read((self.s as usize) + 0x100); self.dec_sp();
+                    mem.read_discard(0x100 | self.s as u16); self.dec_sp();
                 } else { 
-                    mem.write((self.s as usize) + 0x100, (self.pc >> 8) as u8);   self.dec_sp();
+                    mem.write(0x100 | self.s as u16, (self.pc >> 8) as u8);   self.dec_sp();
                 }},
             3 => { 
                 if self.reset_triggered {
-                    mem.read((self.s as usize) + 0x100); self.dec_sp();
+                    mem.read_discard(0x100 | self.s as u16); self.dec_sp();
                 } else {
-                    mem.write((self.s as usize) + 0x100, (self.pc & 0xFF) as u8); self.dec_sp();    
+                    mem.write(0x100 | self.s as u16, (self.pc & 0xFF) as u8); self.dec_sp();    
                 }},
             4 => {
                 if self.reset_triggered {
-                    mem.read((self.s as usize) + 0x100); self.dec_sp();
+                    mem.read_discard(0x100 | self.s as u16); self.dec_sp();
                 } else {
-                    mem.write((self.s as usize) + 0x100, (self.p.pack()) as u8);  self.dec_sp();  
+                    mem.write(0x100 | self.s as u16, (self.p.pack()) as u8);  self.dec_sp();  
                 }},
             5 => { 
                 // the actual vector is chosen late in the process, e.g. http://forum.6502.org/viewtopic.php?t=1797
@@ -632,52 +692,46 @@ impl P65 {
                     if self.irq_triggered { self.irq_triggered = false; };
                 }
             },
-            6 => { let tmp = self.ah_al() as usize; self.set_pch(mem.read(tmp)); },
+            6 => { let tmp = self.ah_al(); self.set_pch(mem.read(tmp)); },
             7 => { self.fetch_op(mem); },        // remember to set I. Is too late here? -> Yes 
             _ => {},
         }
     }
     // An interrupt is more or less like a BRK.
     // Do not clear the interrupt (CLI) before the issuing peripheral interrupt flag is cleared, or will fire twice! 
-    pub fn irq_set(&mut self) {
-        if !self.irq && self.cycle - self.irq_cycle >= 2 {
-            self.irq_cycle = self.cycle;
-            self.irq = true;
-        }
-    }
-    pub fn irq_clear(&mut self) {
-        if  self.irq && self.cycle - self.irq_cycle >= 2 {
+    // These drive the lines from outside, devices on the bus drive them too: the cpu sees the OR
+    pub fn irq_set(&mut self)   { self.irq_pin = true; }
+    pub fn irq_clear(&mut self) { self.irq_pin = false; }
+    pub fn nmi_set(&mut self)   { self.nmi_pin = true; }
+    pub fn nmi_clear(&mut self) { self.nmi_pin = false; }
+
+    fn irq_line(&mut self, level: bool) {
+        if level != self.irq && self.cycle - self.irq_cycle >= 2 {
             self.irq_cycle = self.cycle;
-            self.irq = false;
+            self.irq = level;
         }
     }
     // firing an NMI will get it serviced, then ignored until took down and then up (logically).
     // this is called being "edge sensitive". Must stay up / low for at least two cycles to be sensed.
-    pub fn nmi_set(&mut self) {
-        if !self.nmi && self.cycle - self.nmi_cycle >= 2 {
-            self.nmi_cycle = self.cycle;
-            self.nmi = true;
-        }
-    }
     // please note that once triggered, within 2 cycles, NMI will happen anyway
-    pub fn nmi_clear(&mut self) {
-        if  self.nmi && self.cycle - self.nmi_cycle >= 2 {
+    fn nmi_line(&mut self, level: bool) {
+        if level != self.nmi && self.cycle - self.nmi_cycle >= 2 {
             self.nmi_cycle = self.cycle;
-            self.nmi = false;
+            self.nmi = level;
         }
     }
 
     fn rti_imp<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { mem.read(self.pc as usize);    self.inc_pc(); },   // discard read
-            2 => { mem.read((self.s as usize) + 0x100); self.inc_sp(); },     // discard read too
-            3 => {  let pedante=mem.read(self.s as usize + 0x100);
+            1 => { mem.read_discard(self.pc);    self.inc_pc(); },   // discard read
+            2 => { mem.read_discard(0x100 | self.s as u16); self.inc_sp(); },     // discard read too
+            3 => {  let pedante=mem.read(0x100 | self.s as u16);
                     let tmpb = self.p.b; 
                     self.p.unpack(pedante); self.inc_sp();
                     self.p.b = tmpb;       // b is unaffected by rti & plp
                         },
-            4 => { self.pc = mem.read((self.s as usize) + 0x100) as u16; self.inc_sp(); }, 
-            5 => { self.pc = (self.pc & 0x00ff) | ((mem.read((self.s as usize) + 0x100) as u16) << 8 );  }, 
+            4 => { self.pc = mem.read(0x100 | self.s as u16) as u16; self.inc_sp(); }, 
+            5 => { self.pc = (self.pc & 0x00ff) | ((mem.read(0x100 | self.s as u16) as u16) << 8 );  }, 
             6 => { self.fetch_op(mem); },
             _ => {},
         }
@@ -685,8 +739,8 @@ impl P65 {
 
     fn jmp_abs<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize); self.inc_pc(); },
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc); self.inc_pc(); },
             3 => { self.pc =  self.ah_al();      self.fetch_op(mem); },   // load PC and fetch. only one mem read       
             _ => {},
         }
@@ -694,35 +748,35 @@ impl P65 {
 
     fn jmp_ind<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { self.al =  mem.read(self.pc as usize); self.inc_pc(); },
-            2 => { self.ah =  mem.read(self.pc as usize); self.inc_pc(); },
-            3 => { self.pc =  mem.read(self.ah_al() as usize) as u16; self.al= self.al.wrapping_add(1) },  // carry IS NOT propagated.. don't jump from (XXFF)!
-            4 => { self.pc = (self.pc & 0xFF) |  ((mem.read(self.ah_al() as usize) as u16) << 8); },   // load PC and fetch. only one mem read       
+            1 => { self.al =  mem.read(self.pc); self.inc_pc(); },
+            2 => { self.ah =  mem.read(self.pc); self.inc_pc(); },
+            3 => { self.pc =  mem.read(self.ah_al()) as u16; self.al= self.al.wrapping_add(1) },  // carry IS NOT propagated.. don't jump from (XXFF)!
+            4 => { self.pc = (self.pc & 0xFF) |  ((mem.read(self.ah_al()) as u16) << 8); },   // load PC and fetch. only one mem read       
             5 => { self.fetch_op(mem); },
             _ => {},
         }
     }
     fn rts_imp<M: Memory>(&mut self, mem: &mut M, _: fn(&mut Self)) {
         match self.ts {
-            1 => { mem.read(self.pc as usize);                            self.inc_pc(); },   // discard read
-            2 => { mem.read((self.s as usize) + 0x100);                   self.inc_sp(); },     // discard read too
-            3 => { self.pc =  mem.read((self.s as usize) + 0x100) as u16; self.inc_sp(); }, 
-            4 => { self.pc = (self.pc & 0xFF) |  ((mem.read((self.s as usize) + 0x100) as u16) << 8 );  }, 
-            5 => { mem.read(self.pc as usize);    self.inc_pc(); },   // discard read, inc pc
+            1 => { mem.read_discard(self.pc);                            self.inc_pc(); },   // discard read
+            2 => { mem.read_discard(0x100 | self.s as u16);                   self.inc_sp(); },     // discard read too
+            3 => { self.pc =  mem.read(0x100 | self.s as u16) as u16; self.inc_sp(); }, 
+            4 => { self.pc = (self.pc & 0xFF) |  ((mem.read(0x100 | self.s as u16) as u16) << 8 );  }, 
+            5 => { mem.read_discard(self.pc);    self.inc_pc(); },   // discard read, inc pc
             6 => { self.fetch_op(mem); },
             _ => {},
         }
     }
     fn a5_bxx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { self.v1 =  mem.read(self.pc as usize); self.inc_pc();  opfun(self);   },    // skip to 4 if branch not taken. relative jump is calculated from nextop address
-            2 => { mem.read(self.pc as usize);
+            1 => { self.v1 =  mem.read(self.pc); self.inc_pc();  opfun(self);   },    // skip to 4 if branch not taken. relative jump is calculated from nextop address
+            2 => { mem.read_discard(self.pc);
                         let newpc = self.pc as i16 as i32 + self.v1 as i8 as i32;  // we extend sign
                         self.pc =  (self.pc & 0xFF00) | (newpc & 0xFF) as u16;   // modify pcl only
                         if (newpc & 0xFF00) as u16 == self.pc & 0xFF00 { self.ts += 1; }   // skip if not page
                         self.v2 =  ((newpc & 0xFF00) >> 8) as u8;   // save pch for later
             },
-            3 => { mem.read(self.pc as usize);  
+            3 => { mem.read_discard(self.pc);  
                         self.pc =  self.pc & 0xFF | ((self.v2 as u16) << 8);
             },                          // eventually complete carry propagation
             4 => { self.fetch_op(mem); },                                                                // finally fetch new opcode
@@ -732,9 +786,9 @@ impl P65 {
 
     fn a5_plx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { mem.read(self.pc as usize); },                                 // discard read
-            2 => { mem.read((self.s as usize) + 0x100); self.inc_sp();  },        // discard read
-            3 => { self.v1 =  mem.read((self.s as usize) +0x100); },
+            1 => { mem.read_discard(self.pc); },                                 // discard read
+            2 => { mem.read_discard(0x100 | self.s as u16); self.inc_sp();  },        // discard read
+            3 => { self.v1 =  mem.read(0x100 | self.s as u16); },
             4 => { opfun(self);   self.fetch_op(mem);  },                              // place a or p in its right place
             _ => {},
         }
@@ -742,8 +796,8 @@ impl P65 {
 
     fn a5_phx<M: Memory>(&mut self, mem: &mut M, opfun: OpcodeF) {
         match self.ts {
-            1 => { mem.read(self.pc as usize); },                                 // discard read (don't incpc)
-            2 => { opfun(self);  mem.write((self.s as usize) + 0x100, self.v1);     self.dec_sp(); },
+            1 => { mem.read_discard(self.pc); },                                 // discard read (don't incpc)
+            2 => { opfun(self);  mem.write(0x100 | self.s as u16, self.v1);     self.dec_sp(); },
             3 => { self.fetch_op(mem); },
             _ => {},
         }
@@ -776,8 +830,20 @@ impl P65 {
         }
     }    
 
+    // a real chip comes out of reset with random registers, D flag included, and only I set.
+    // Call after reset to check that firmware doesn't assume otherwise
+    pub fn set_registers(&mut self, a: u8, x: u8, y: u8, s: u8, p: u8) {
+        self.a = a;
+        self.x = x;
+        self.y = y;
+        self.s = s;
+        self.p.unpack(p);
+        self.p.i = true;
+    }
+
     pub fn reset<M: Memory>(&mut self, mem: &mut M) {
         self.s =   0xFD;
+        self.p.i = true;
         self.op =  0x00;
         self.al =  mem.read(0xFFFC);
         self.ah =  mem.read(0xFFFD);
@@ -785,6 +851,12 @@ impl P65 {
         self.fetch_op(mem);
         self.tick();
         self.cycle = 8;
+        mem.fault();        // not a program running yet, nobody to stop
+    }
+
+    // address of the instruction being executed, useful to report who did what
+    pub fn op_pc(&self) -> u16 {
+        self.current_op_pc
     }
 
     // directly jump to address, reset T count and fetch
@@ -792,6 +864,7 @@ impl P65 {
         self.pc =  address;
         self.fetch_op(mem);
         self.tick();
+        mem.fault();
     }
 
     // QUESTION: CAN A NMI interrupt another NMI ?
@@ -804,10 +877,28 @@ impl P65 {
         }
     }
 
-    /* run will run count cycles, eventually stopping in the midst of an instruction */
-    pub fn run<M: Memory>(&mut self, mem: &mut M, count: u64) -> u64 {
-        self.check_interrupts(); // FIXME: interrupts should be polled at the end of T1 or early T2. see: https://wiki.nesdev.com/w/index.php/CPU_interrupts
+    /* run will run count cycles, eventually stopping in the midst of an instruction.
+     * A bus fault stops it early, right after the cycle that caused it.
+     * The bus is looked at only when its clock says so, the lines are kept in between */
+    pub fn run<M: Memory>(&mut self, mem: &mut M, count: u64) -> Result<u64, Stop> {
+        self.bus_irq = mem.irq();       // the host may have moved them
+        self.bus_nmi = mem.nmi();
         for _ in 0 .. count {
+            if self.stall > 0 {     // slow memory, device or DMA: the cycle is stretched, nothing else happens
+                self.stall -= 1;
+                self.cycle_inc();
+                if mem.tick() {
+                    self.bus_irq = mem.irq();
+                    self.bus_nmi = mem.nmi();
+                    self.stall = self.stall.saturating_add(mem.wait_states());   // DMA while stalled
+                }
+                continue;
+            }
+            let irq = self.irq_pin || self.bus_irq;
+            self.irq_line(irq);
+            let nmi = self.nmi_pin || self.bus_nmi;
+            self.nmi_line(nmi);
+            self.check_interrupts(); // FIXME: interrupts should be polled at the end of T1 or early T2. see: https://wiki.nesdev.com/w/index.php/CPU_interrupts
 
             let opaddr: AddrModeF<M> = P65::decode_addr_mode::<M>(self.op);
             let opfun: OpcodeF = P65::decode_op(self.op);
@@ -819,8 +910,17 @@ impl P65 {
                 self.p.b = false;     // we clear B here, because of entering BRK at T2 (and to simulate BRK/IRQ & IRQ/NMI B shadowing)
             }
             self.tick();
+            if !mem.tick() {
+                continue;
+            }
+            self.bus_irq = mem.irq();
+            self.bus_nmi = mem.nmi();
+            self.stall = mem.wait_states();
+            if let Some((fault, address)) = mem.fault() {
+                return Err(Stop { fault, address, pc: self.current_op_pc, cycle: self.cycle });
+            }
         }
-        self.cycle
+        Ok(self.cycle)
     }
 
 
@@ -836,13 +936,15 @@ impl P65 {
      * c) set 0xFFFE/0xFFFF and reset
      *
      * Note that by repetitive calling to run, step may be substantially slower
+     * Wait states after the fetch keep the processor in T1: the step ends when they are over.
      */
-    pub fn step<M: Memory>(&mut self, mem: &mut M, count: u64) {
+    pub fn step<M: Memory>(&mut self, mem: &mut M, count: u64) -> Result<(), Stop> {
         let mut count = count;
         while count > 0 {
-            self.run(mem,1);
-            if self.ts == 1 { count -= 1; }
+            self.run(mem,1)?;
+            if self.ts == 1 && self.stall == 0 { count -= 1; }
         }
+        Ok(())
     }
 }
 
@@ -0,0 +1,372 @@
+/*
+ * MOS 6551 ACIA, the serial port of many a 6502 board. Four registers: data, status
+ * (a write is a programmed reset), command and control.
+ *
+ * Characters take as long as the baud rate says: a frame is start bit, data bits, parity and
+ * stop bits, each clock / baud cycles long, clock being the cpu one. The "external clock" rate
+ * is taken as 115200 baud. Received bytes come from the host side, one frame after the other as
+ * long as RTS is asserted, like a terminal doing hardware flow control: a program not reading
+ * fast enough gets overruns. The line is clean, no parity or framing errors ever. DCD and DSR
+ * are asserted.
+ *
+ * The WDC 65C51 has a famous bug: TDRE is stuck at 1 and there is no transmit interrupt, so
+ * firmware must wait a character time between writes. Writing while a character is going out
+ * restarts the shifter with the new one, and the old one is lost.
+ */
+
+use std::collections::VecDeque;
+use std::io::Write;
+use devices::{Device, Keys};
+
+// status
+const OVERRUN: u8 = 0x04;
+const RDRF: u8 = 0x08;
+const TDRE: u8 = 0x10;
+const IRQ: u8 = 0x80;
+
+const BAUD: [u32; 16] = [115200, 50, 75, 110, 135, 150, 300, 600, 1200, 1800, 2400, 3600, 4800, 7200, 9600, 19200];
+
+pub struct Acia {
+    clock: u64,             // cpu clock, Hz
+    wdc: bool,              // the 65C51 transmitter bug
+    input: Keys,            // bytes waiting on the host side
+    output: Box<dyn Write>,
+    command: u8,
+    control: u8,
+    status: u8,
+    rdr: u8,
+    tdr: Option<u8>,        // waiting for the shifter
+    tx: Option<u8>,         // in the shifter
+    tx_left: u64,           // cycles to its last stop bit
+    rx: Option<u8>,         // on the line
+    rx_left: u64,
+}
+
+impl Acia {
+    pub fn new(clock: u64, wdc: bool, input: Keys, output: Box<dyn Write>) -> Acia {
+        Acia {
+            clock,
+            wdc,
+            input,
+            output,
+            command: 0,
+            control: 0,
+            status: TDRE,
+            rdr: 0,
+            tdr: None,
+            tx: None,
+            tx_left: 0,
+            rx: None,
+            rx_left: 0,
+        }
+    }
+
+    fn data_bits(&self) -> u64 {
+        8 - ((self.control >> 5) & 3) as u64
+    }
+    // cycles for a character
+    fn frame(&self) -> u64 {
+        let parity = self.command & 0x20 != 0;
+        let stop = if self.control & 0x80 == 0 || (self.data_bits() == 8 && parity) { 1 } else { 2 };
+        let bits = 1 + self.data_bits() + parity as u64 + stop;
+        (bits * self.clock / BAUD[(self.control & 0x0F) as usize] as u64).max(1)
+    }
+    fn mask(&self) -> u8 {
+        (0xFFu16 >> (8 - self.data_bits())) as u8
+    }
+    fn receiver_on(&self) -> bool {
+        self.command & 0x01 != 0
+    }
+    // RTS is asserted unless TIC is 00, which echo mode wants with RTS asserted all the same
+    fn rts(&self) -> bool {
+        self.command & 0x1C != 0
+    }
+    fn tx_irq_on(&self) -> bool {
+        !self.wdc && self.command & 0x0C == 0x04
+    }
+    fn rx_irq_on(&self) -> bool {
+        self.receiver_on() && self.command & 0x02 == 0
+    }
+
+    fn send(&mut self, v: u8) {
+        // the host may well be gone, nothing to do about it
+        let _ = self.output.write(&[v]);
+        let _ = self.output.flush();
+    }
+    // TDR into the shifter, if free
+    fn load_shifter(&mut self) {
+        if self.tx.is_none() && self.tdr.is_some() {
+            self.tx = self.tdr.take();
+            self.tx_left = self.frame();
+            self.status |= TDRE;
+            if self.tx_irq_on() {
+                self.status |= IRQ;
+            }
+        }
+    }
+    // a byte from the host on the line, if we may
+    fn start_rx(&mut self) {
+        if self.rx.is_none() && self.receiver_on() && self.rts() {
+            self.rx = self.input.borrow_mut().pop_front();
+            self.rx_left = self.frame();
+        }
+    }
+    fn tx_done(&mut self) {
+        if let Some(v) = self.tx.take() {
+            let v = v & self.mask();
+            self.send(v);
+        }
+        self.load_shifter();
+    }
+    fn rx_done(&mut self) {
+        if let Some(v) = self.rx.take() {
+            if self.status & RDRF != 0 {
+                self.status |= OVERRUN;             // lost
+            } else {
+                self.rdr = v & self.mask();
+                self.status |= RDRF;
+                if self.rx_irq_on() {
+                    self.status |= IRQ;
+                }
+                if self.command & 0x1C == 0x10 {
+                    let echo = self.rdr;
+                    self.send(echo);                // echo mode
+                }
+            }
+        }
+    }
+}
+
+impl Device for Acia {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.peek(reg);
+        match reg & 3 {
+            0 => self.status &= !(RDRF | OVERRUN),
+            1 => self.status &= !IRQ,
+            _ => {}
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 3 {
+            0 => {
+                if self.wdc {
+                    // straight into the shifter, whatever was in there
+                    self.tx = Some(v);
+                    self.tx_left = self.frame();
+                } else {
+                    self.tdr = Some(v);
+                    self.status &= !TDRE;
+                    self.load_shifter();
+                }
+            }
+            1 => {
+                // programmed reset
+                self.command &= 0xE0;
+                self.status &= !OVERRUN;
+            }
+            2 => {
+                self.command = v;
+                if self.tx_irq_on() && self.status & TDRE != 0 {
+                    self.status |= IRQ;
+                }
+            }
+            _ => self.control = v,
+        }
+        self.start_rx();
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        match reg & 3 {
+            0 => self.rdr,
+            1 => if self.wdc { self.status | TDRE } else { self.status },
+            2 => self.command,
+            _ => self.control,
+        }
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while cycles > 0 {
+            let mut k = cycles;
+            if self.tx.is_some() {
+                k = k.min(self.tx_left);
+            }
+            if self.rx.is_some() {
+                k = k.min(self.rx_left);
+            }
+            cycles -= k;
+            if self.tx.is_some() {
+                self.tx_left -= k;
+                if self.tx_left == 0 {
+                    self.tx_done();
+                }
+            }
+            if self.rx.is_some() {
+                self.rx_left -= k;
+                if self.rx_left == 0 {
+                    self.rx_done();
+                    self.start_rx();        // back to back
+                }
+            }
+        }
+        self.start_rx();
+    }
+    fn next_event(&self) -> Option<u64> {
+        let mut n = None;
+        if self.tx.is_some() {
+            n = Some(self.tx_left);
+        }
+        if self.rx.is_some() {
+            n = Some(n.map_or(self.rx_left, |n: u64| n.min(self.rx_left)));
+        } else if self.receiver_on() && self.rts() && !self.input.borrow().is_empty() {
+            n = Some(1);                    // host has something, get it on the line
+        }
+        n
+    }
+    fn irq(&self) -> bool {
+        self.status & IRQ != 0
+    }
+}
+
+// host side for the command line: in=file, out=file, clock=hz, wdc. Console otherwise
+pub fn create(opts: &[&str], keys: &Keys) -> Result<Acia, String> {
+    let mut clock = 1_000_000;
+    let mut wdc = false;
+    let mut input = keys.clone();
+    let mut output: Box<dyn Write> = Box::new(::std::io::stdout());
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("wdc"), None) => wdc = true,
+            (Some("clock"), Some(v)) => {
+                clock = v.parse::<u64>().ok().and_then(|v| if v > 0 { Some(v) } else { None })
+                         .ok_or(format!("bad clock {}", v))?;
+            }
+            (Some("in"), Some(f)) => {
+                let data = ::std::fs::read(f).map_err(|e| format!("{}: {}", f, e))?;
+                input = Keys::new(::std::cell::RefCell::new(data.into_iter().collect::<VecDeque<u8>>()));
+            }
+            (Some("out"), Some(f)) => {
+                output = Box::new(::std::fs::File::create(f).map_err(|e| format!("{}: {}", f, e))?);
+            }
+            _ => return Err(format!("unknown acia option {}", o)),
+        }
+    }
+    Ok(Acia::new(clock, wdc, input, output))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use devices::{Device, Keys};
+    use devices::acia::*;
+
+    #[derive(Clone, Default)]
+    struct Tape(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Tape {
+        fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(b);
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // 9600 baud at 96kHz: 10 cycles a bit, 100 for 8N1
+    fn acia(wdc: bool, input: &[u8]) -> (Acia, Tape) {
+        let tape = Tape::default();
+        let keys = Keys::new(RefCell::new(input.iter().cloned().collect()));
+        let mut acia = Acia::new(96_000, wdc, keys, Box::new(tape.clone()));
+        acia.write(3, 0x1E);
+        (acia, tape)
+    }
+
+    #[test]
+    fn transmits_a_frame_at_a_time() {
+        let (mut acia, tape) = acia(false, b"");
+        acia.write(2, 0x0B);
+        acia.write(0, b'A');
+        acia.write(0, b'B');
+        assert_eq!(acia.read(1) & 0x10, 0);     // B waits in TDR
+        assert_eq!(acia.next_event(), Some(100));
+        acia.tick(99);
+        assert!(tape.0.borrow().is_empty());
+        acia.tick(1);
+        assert_eq!(*tape.0.borrow(), b"A");
+        assert_eq!(acia.read(1) & 0x10, 0x10);  // B in the shifter
+        acia.tick(100);
+        assert_eq!(*tape.0.borrow(), b"AB");
+        assert_eq!(acia.next_event(), None);
+    }
+
+    #[test]
+    fn transmit_interrupt() {
+        let (mut acia, _) = acia(false, b"");
+        acia.write(2, 0x07);
+        assert!(acia.irq());
+        assert_eq!(acia.read(1), 0x90);
+        assert!(!acia.irq());                   // reading status acknowledges
+        acia.write(0, b'A');
+        assert!(acia.irq());                    // straight into the shifter, TDR empty again
+    }
+
+    #[test]
+    fn receives_with_overrun() {
+        let (mut acia, _) = acia(false, b"hi");
+        acia.write(2, 0x09);
+        acia.tick(100);
+        assert!(acia.irq());
+        assert_eq!(acia.read(1), 0x98);
+        acia.tick(100);
+        assert_eq!(acia.read(1), 0x1C);         // i is lost
+        assert_eq!(acia.read(0), b'h');
+        assert_eq!(acia.read(1), 0x10);
+        assert_eq!(acia.next_event(), None);
+    }
+
+    #[test]
+    fn receive_waits_for_rts() {
+        let (mut acia, _) = acia(false, b"h");
+        acia.write(2, 0x01);
+        acia.tick(1000);
+        assert_eq!(acia.read(1) & 0x08, 0);
+        acia.write(2, 0x0B);
+        acia.tick(100);
+        assert_eq!(acia.read(0), b'h');
+    }
+
+    #[test]
+    fn seven_bits_two_stops_and_echo() {
+        let (mut acia, tape) = acia(false, b"\xC1");
+        acia.write(3, 0xBE);                    // 7 data bits, 2 stop bits: 100 cycles
+        acia.write(2, 0x13);
+        acia.tick(100);
+        assert_eq!(acia.read(0), 0x41);
+        assert_eq!(*tape.0.borrow(), b"A");
+    }
+
+    #[test]
+    fn wdc_has_tdre_stuck() {
+        let (mut acia, tape) = acia(true, b"");
+        acia.write(2, 0x07);
+        assert!(!acia.irq());
+        acia.write(0, b'A');
+        assert_eq!(acia.read(1), 0x10);
+        acia.tick(50);
+        acia.write(0, b'B');                    // A is lost
+        acia.tick(100);
+        assert_eq!(*tape.0.borrow(), b"B");
+        assert!(!acia.irq());
+    }
+
+    #[test]
+    fn programmed_reset() {
+        let (mut acia, _) = acia(false, b"");
+        acia.write(2, 0xEB);
+        acia.write(1, 0);
+        assert_eq!(acia.read(2), 0xE0);
+        assert_eq!(acia.read(3), 0x1E);
+    }
+}
@@ -0,0 +1,357 @@
+/*
+ * A bitmap display with nothing to show it on: the pixels live in the device window, the frames go
+ * to image files. Graphics routines can be checked by comparing pictures, with no display around.
+ *
+ * The window is the bitmap, row after row with no gaps, then the registers. With less than 8 bits
+ * per pixel the leftmost pixel is in the high bits of the byte. Pixels are palette indexes, the
+ * palette has 2^bpp entries of 8 bit red, green and blue. At power on it is black and white for
+ * 1 bpp, four greys for 2, the xterm colours for 4 and 8.
+ *
+ *   +0  control, write: bit 0 enables the vertical blank interrupt
+ *       status, read: bit 7 vertical blank since the last read (reading clears it and the
+ *       interrupt), bit 0 as written
+ *   +1  snapshot, write: save the frame now
+ *   +2  palette index
+ *   +3  palette data: red, green, blue, then on to the next index. Reads go the same way
+ *   +4  frame count, low byte, one more every vertical blank
+ *
+ * A frame is 16667 cycles, 60Hz at 1MHz, unless told otherwise. Snapshots go to the file named
+ * with snap=, every so many cycles if asked to and whenever the program says. A # in the name is
+ * the number of the snapshot, 6 digits from 000000, else the same file is written over. One
+ * that can't be written stops the run with a device error.
+ */
+
+use cpu::BusFault;
+use devices::Device;
+use devices::image;
+
+const FRAME: u64 = 16667;       // cycles
+const REGISTERS: usize = 5;
+
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    bpp: usize,
+    m: Vec<u8>,
+    palette: Vec<[u8; 3]>,
+    index: usize,
+    component: usize,
+    control: u8,
+    vblank: bool,
+    frames: u64,
+    frame: u64,         // cycles
+    into: u64,          // cycles into the frame
+    snap: Option<String>,
+    every: Option<u64>,
+    since: u64,         // cycles since the last periodic snapshot
+    shots: u32,
+    fault: Option<BusFault>,
+}
+
+// the xterm 256 colours: 16 of the PC, a 6x6x6 cube, 24 greys
+fn xterm(i: usize) -> [u8; 3] {
+    const BASIC: [u32; 16] = [0x000000, 0x800000, 0x008000, 0x808000, 0x000080, 0x800080, 0x008080, 0xC0C0C0,
+                              0x808080, 0xFF0000, 0x00FF00, 0xFFFF00, 0x0000FF, 0xFF00FF, 0x00FFFF, 0xFFFFFF];
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    match i {
+        0..=15 => [(BASIC[i] >> 16) as u8, (BASIC[i] >> 8) as u8, BASIC[i] as u8],
+        16..=231 => [LEVELS[(i - 16) / 36], LEVELS[(i - 16) / 6 % 6], LEVELS[(i - 16) % 6]],
+        _ => {
+            let g = (8 + (i - 232) * 10) as u8;
+            [g, g, g]
+        }
+    }
+}
+
+impl Bitmap {
+    // width * bpp must be whole bytes
+    pub fn new(width: usize, height: usize, bpp: usize) -> Bitmap {
+        let palette = match bpp {
+            1 => vec![[0; 3], [0xFF; 3]],
+            2 => vec![[0; 3], [0x55; 3], [0xAA; 3], [0xFF; 3]],
+            _ => (0..1 << bpp).map(xterm).collect(),
+        };
+        Bitmap {
+            width,
+            height,
+            bpp,
+            m: vec![0; width * bpp / 8 * height],
+            palette,
+            index: 0,
+            component: 0,
+            control: 0,
+            vblank: false,
+            frames: 0,
+            frame: FRAME,
+            into: 0,
+            snap: None,
+            every: None,
+            since: 0,
+            shots: 0,
+            fault: None,
+        }
+    }
+
+    // the window: bitmap and registers
+    pub fn len(&self) -> usize {
+        self.m.len() + REGISTERS
+    }
+
+    pub fn set_frame(&mut self, cycles: u64) {
+        self.frame = cycles.max(1);
+    }
+
+    // snapshots to file, numbered at #, and every so many cycles if some
+    pub fn snapshots(&mut self, file: &str, every: Option<u64>) {
+        self.snap = Some(file.to_string());
+        self.every = every.map(|e| e.max(1));
+    }
+
+    // the picture, 3 bytes of RGB a pixel
+    pub fn rgb(&self) -> Vec<u8> {
+        let per_byte = 8 / self.bpp;
+        let mask = ((1u16 << self.bpp) - 1) as u8;
+        let mut v = Vec::with_capacity(self.width * self.height * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let b = self.m[(y * self.width + x) / per_byte];
+                let shift = (per_byte - 1 - x % per_byte) * self.bpp;
+                v.extend_from_slice(&self.palette[(b >> shift & mask) as usize]);
+            }
+        }
+        v
+    }
+
+    fn snapshot(&mut self) {
+        let name = match self.snap {
+            Some(ref s) => image::numbered(s, self.shots),
+            None => return,
+        };
+        self.shots += 1;
+        if image::save(&name, self.width, self.height, &self.rgb()).is_err() {
+            self.fault = Some(BusFault::Device("snapshot not written"));
+        }
+    }
+
+    fn peek_register(&self, reg: usize) -> u8 {
+        match reg {
+            0 => (self.vblank as u8) << 7 | self.control & 1,
+            2 => self.index as u8,
+            3 => self.palette[self.index][self.component],
+            4 => self.frames as u8,
+            _ => 0xFF,
+        }
+    }
+
+    fn next_component(&mut self) {
+        self.component += 1;
+        if self.component == 3 {
+            self.component = 0;
+            self.index = (self.index + 1) % self.palette.len();
+        }
+    }
+}
+
+impl Device for Bitmap {
+    fn read(&mut self, reg: u16) -> u8 {
+        let r = reg as usize;
+        if r < self.m.len() {
+            return self.m[r];
+        }
+        let v = self.peek_register(r - self.m.len());
+        match r - self.m.len() {
+            0 => self.vblank = false,
+            3 => self.next_component(),
+            _ => {}
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        let r = reg as usize;
+        if r < self.m.len() {
+            self.m[r] = v;
+            return;
+        }
+        match r - self.m.len() {
+            0 => self.control = v & 1,
+            1 => self.snapshot(),
+            2 => {
+                self.index = v as usize % self.palette.len();
+                self.component = 0;
+            }
+            3 => {
+                self.palette[self.index][self.component] = v;
+                self.next_component();
+            }
+            _ => {}
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        let r = reg as usize;
+        if r < self.m.len() { self.m[r] } else { self.peek_register(r - self.m.len()) }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.into += cycles;
+        if self.into >= self.frame {
+            self.frames += self.into / self.frame;
+            self.into %= self.frame;
+            self.vblank = true;
+        }
+        if let Some(every) = self.every {
+            self.since += cycles;
+            while self.since >= every {
+                self.since -= every;
+                self.snapshot();
+            }
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        let vblank = if self.control & 1 != 0 && !self.vblank { Some(self.frame - self.into) } else { None };
+        let snap = self.every.map(|e| e - self.since);
+        match (vblank, snap) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+    fn irq(&self) -> bool {
+        self.control & 1 != 0 && self.vblank
+    }
+    fn fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+// for the command line: size=widthxheight (320x200), bpp=1|2|4|8 (1), frame=cycles (16667),
+// snap=file (.png or .ppm, # numbered), every=cycles
+pub fn create(opts: &[&str]) -> Result<Bitmap, String> {
+    let (mut width, mut height, mut bpp) = (320, 200, 1);
+    let mut frame = FRAME;
+    let mut snap = None;
+    let mut every = None;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("size"), Some(v)) => {
+                let size: Vec<usize> = v.split('x').filter_map(|n| n.parse().ok()).collect();
+                match size[..] {
+                    [w, h] if w > 0 && h > 0 => {
+                        width = w;
+                        height = h;
+                    }
+                    _ => return Err(format!("bad bitmap size {}", v)),
+                }
+            }
+            (Some("bpp"), Some(v)) => match v.parse() {
+                Ok(b @ 1) | Ok(b @ 2) | Ok(b @ 4) | Ok(b @ 8) => bpp = b,
+                _ => return Err(format!("bad bitmap bpp {}", v)),
+            },
+            (Some("frame"), Some(v)) => match v.parse() {
+                Ok(n) if n > 0 => frame = n,
+                _ => return Err(format!("bad bitmap frame {}", v)),
+            },
+            (Some("snap"), Some(v)) if !v.is_empty() => snap = Some(v.to_string()),
+            (Some("every"), Some(v)) => match v.parse() {
+                Ok(n) if n > 0 => every = Some(n),
+                _ => return Err(format!("bad bitmap every {}", v)),
+            },
+            _ => return Err(format!("unknown bitmap option {}", o)),
+        }
+    }
+    let row = width.checked_mul(bpp);
+    if row.is_some_and(|r| r % 8 != 0) {
+        return Err(format!("bitmap rows of {} pixels at {} bpp aren't whole bytes", width, bpp));
+    }
+    match row.and_then(|r| (r / 8).checked_mul(height)) {
+        Some(n) if n + REGISTERS <= 65536 => {}
+        _ => return Err(format!("bitmap {}x{} at {} bpp is larger than 64KB", width, height, bpp)),
+    }
+    if every.is_some() && snap.is_none() {
+        return Err("bitmap every needs snap=file".to_string());
+    }
+    let mut b = Bitmap::new(width, height, bpp);
+    b.set_frame(frame);
+    if let Some(s) = snap {
+        b.snapshots(&s, every);
+    }
+    Ok(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use cpu::BusFault;
+    use devices::Device;
+    use devices::bitmap::*;
+
+    #[test]
+    fn pixels_through_the_palette() {
+        let mut b = Bitmap::new(8, 1, 1);
+        b.write(0, 0xA0);
+        assert_eq!(b.rgb()[..9], [0xFF, 0xFF, 0xFF, 0, 0, 0, 0xFF, 0xFF, 0xFF]);
+        let mut b = Bitmap::new(4, 1, 2);
+        b.write(0, 0x1B);
+        assert_eq!(b.rgb(), [0, 0, 0, 0x55, 0x55, 0x55, 0xAA, 0xAA, 0xAA, 0xFF, 0xFF, 0xFF]);
+        let mut b = Bitmap::new(2, 1, 4);
+        b.write(0, 0x9C);
+        assert_eq!(b.rgb(), [0xFF, 0, 0, 0, 0, 0xFF]);
+        let mut b = Bitmap::new(2, 1, 8);
+        b.write(0, 0xFF);
+        b.write(1, 196);
+        assert_eq!(b.rgb(), [0xEE, 0xEE, 0xEE, 0xFF, 0, 0]);
+    }
+
+    #[test]
+    fn palette_registers() {
+        let mut b = Bitmap::new(2, 1, 8);
+        assert_eq!(b.len(), 2 + 5);
+        b.write(2 + 2, 0xFF);
+        for &v in &[1, 2, 3, 4, 5, 6] {
+            b.write(2 + 3, v);          // FF, then around to 0
+        }
+        b.write(2 + 2, 0xFF);
+        let rgb: Vec<u8> = (0..6).map(|_| b.read(2 + 3)).collect();
+        assert_eq!(rgb, [1, 2, 3, 4, 5, 6]);
+        b.write(0, 0xFF);
+        assert_eq!(b.rgb(), [1, 2, 3, 4, 5, 6]);
+    }
+
+    #[test]
+    fn vertical_blank() {
+        let mut b = Bitmap::new(8, 1, 1);
+        b.set_frame(100);
+        assert_eq!(b.next_event(), None);
+        b.write(1, 1);
+        assert_eq!(b.next_event(), Some(100));
+        b.tick(99);
+        assert!(!b.irq());
+        b.tick(1);
+        assert!(b.irq());
+        assert_eq!(b.next_event(), None);
+        assert_eq!(b.read(1), 0x81);
+        assert!(!b.irq());
+        b.tick(250);
+        assert_eq!(b.read(1 + 4), 3);
+        assert_eq!(b.next_event(), None);
+        b.read(1);
+        assert_eq!(b.next_event(), Some(50));
+    }
+
+    #[test]
+    fn sizes() {
+        assert!(create(&["size=7x1"]).is_err());
+        assert!(create(&["size=7x1", "bpp=8"]).is_ok());
+        assert!(create(&["size=256x255", "bpp=8"]).is_ok());
+        assert!(create(&["size=256x256", "bpp=8"]).is_err());
+        assert!(create(&["size=4611686018427387904x1", "bpp=4"]).is_err());
+        assert!(create(&["size=8x2305843009213693952"]).is_err());
+        assert!(create(&["every=10"]).is_err());
+    }
+
+    #[test]
+    fn snapshots_that_cant_be_written_fault() {
+        let mut b = Bitmap::new(8, 1, 1);
+        b.snapshots("/nonexistent/frame#.ppm", None);
+        b.write(1 + 1, 0);
+        assert!(b.fault() == Some(BusFault::Device("snapshot not written")));
+        assert!(b.fault().is_none());
+    }
+}
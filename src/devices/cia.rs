@@ -0,0 +1,631 @@
+/*
+ * MOS 6526 CIA, Complex Interface Adapter: two 8 bit ports, two 16 bit interval timers that can
+ * be chained, a BCD time of day clock with alarm, a serial shift register and the interrupt
+ * control register. 16 registers, mirrored over the window.
+ *
+ * Timers count phi2 cycles, CNT rising edges or, timer B only, timer A underflows. They go from
+ * the latch down to 0, and underflow on the next count reloading the latch: a period of N + 1.
+ * Starts, stops and loads take effect at once, the chip pipeline delays of a cycle or two are not
+ * there. The old 6526 asserts /IRQ one cycle after the flag is set, the 6526A/8521 in the same
+ * cycle: that is the difference timing tests see.
+ *
+ * The TOD clock runs from the mains frequency, 50 or 60 Hz from the cpu clock, divided by 5 or 6
+ * as CRA bit 7 says. A mismatch makes it run late or early, like on the real thing.
+ * Pins are levels, driven and read by the host side like on the VIA. Inputs float high.
+ */
+
+use devices::Device;
+
+// interrupt flags
+const TA: u8 = 0x01;
+const TB: u8 = 0x02;
+const ALARM: u8 = 0x04;
+const SP: u8 = 0x08;
+const FLAG: u8 = 0x10;
+
+struct Timer {
+    counter: u16,
+    latch: u16,
+    cr: u8,
+    toggle: bool,       // output, toggle mode
+    pulse: bool,        // output, pulse mode: high for the underflow cycle
+}
+
+impl Timer {
+    fn new() -> Timer {
+        Timer { counter: 0xFFFF, latch: 0xFFFF, cr: 0, toggle: false, pulse: false }
+    }
+    fn running(&self) -> bool {
+        self.cr & 0x01 != 0
+    }
+    fn output(&self) -> bool {
+        if self.cr & 0x04 != 0 { self.toggle } else { self.pulse }
+    }
+    // a count, true on underflow
+    fn count(&mut self) -> bool {
+        if self.counter > 0 {
+            self.counter -= 1;
+            return false;
+        }
+        self.counter = self.latch;
+        self.toggle = !self.toggle;
+        self.pulse = true;
+        if self.cr & 0x08 != 0 {
+            self.cr &= !0x01;       // one shot
+        }
+        true
+    }
+    fn write_cr(&mut self, v: u8) {
+        if v & 0x10 != 0 {
+            self.counter = self.latch;      // force load, a strobe
+        }
+        if v & 0x01 != 0 && !self.running() {
+            self.toggle = true;
+        }
+        self.cr = v & !0x10;
+    }
+    fn write_high(&mut self, v: u8) {
+        self.latch = self.latch & 0x00FF | (v as u16) << 8;
+        if !self.running() {
+            self.counter = self.latch;
+            if self.cr & 0x08 != 0 {
+                self.write_cr(self.cr | 0x01);  // one shot starts on its own
+            }
+        }
+    }
+}
+
+fn bcd_inc(v: u8) -> u8 {
+    if v & 0x0F == 9 { (v & 0xF0) + 0x10 } else { v + 1 }
+}
+
+pub struct Cia {
+    old: bool,          // 6526, not 6526A
+    pra: u8,
+    prb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    ta: Timer,
+    tb: Timer,
+    tod: [u8; 4],       // tenths, seconds, minutes, hours with PM in bit 7
+    alarm: [u8; 4],
+    tod_latch: Option<[u8; 4]>,     // reading hours freezes the outputs until tenths are read
+    tod_stopped: bool,              // writing hours stops the clock until tenths are written
+    tod_div: u8,
+    tod_left: u64,      // cycles to the next mains tick
+    tod_period: u64,
+    sdr: u8,
+    sr: u8,             // shifter
+    sr_bits: u8,        // half bits out to go, or bits in so far
+    sdr_full: bool,     // output: written while shifting, goes next
+    cnt_in: bool,
+    cnt_out: bool,      // serial clock when shifting out
+    sp_in: bool,
+    sp_out: bool,
+    flag_in: bool,
+    icr: u8,
+    mask: u8,
+    irq_line: bool,
+    irq_pending: bool,  // old CIA: the line goes down next cycle
+}
+
+impl Cia {
+    pub fn new(clock: u64, mains: u64, old: bool) -> Cia {
+        let period = (clock / mains).max(1);
+        Cia {
+            old,
+            pra: 0, prb: 0, ddra: 0, ddrb: 0, pa_in: 0xFF, pb_in: 0xFF,
+            ta: Timer::new(),
+            tb: Timer::new(),
+            tod: [0, 0, 0, 0x01],
+            alarm: [0; 4],
+            tod_latch: None,
+            tod_stopped: true,      // until the time is set
+            tod_div: 0,
+            tod_left: period,
+            tod_period: period,
+            sdr: 0, sr: 0, sr_bits: 0, sdr_full: false,
+            cnt_in: true, cnt_out: true, sp_in: true, sp_out: true, flag_in: true,
+            icr: 0, mask: 0, irq_line: false, irq_pending: false,
+        }
+    }
+
+    // pin levels, PB6 and PB7 can be timer outputs
+    pub fn port_a(&self) -> u8 {
+        self.pra & self.ddra | self.pa_in & !self.ddra
+    }
+    pub fn port_b(&self) -> u8 {
+        let mut v = self.prb & self.ddrb | self.pb_in & !self.ddrb;
+        if self.ta.cr & 0x02 != 0 {
+            v = v & !0x40 | (self.ta.output() as u8) << 6;
+        }
+        if self.tb.cr & 0x02 != 0 {
+            v = v & !0x80 | (self.tb.output() as u8) << 7;
+        }
+        v
+    }
+
+    fn sp_output(&self) -> bool {
+        self.ta.cr & 0x40 != 0
+    }
+
+    fn raise(&mut self, f: u8) {
+        self.icr |= f;
+        if self.icr & self.mask != 0 && !self.irq_line {
+            if self.old {
+                self.irq_pending = true;
+            } else {
+                self.irq_line = true;
+            }
+        }
+    }
+
+    fn ta_underflow(&mut self) {
+        self.raise(TA);
+        if self.sp_output() && self.sr_bits > 0 {
+            // serial clock is half the underflow rate: a bit out on falling CNT
+            self.cnt_out = !self.cnt_out;
+            if !self.cnt_out {
+                self.sp_out = self.sr & 0x80 != 0;
+                self.sr <<= 1;
+            }
+            self.sr_bits -= 1;
+            if self.sr_bits == 0 {
+                self.raise(SP);
+                if self.sdr_full {
+                    self.sdr_full = false;
+                    self.sr = self.sdr;
+                    self.sr_bits = 16;
+                }
+            }
+        }
+        // chained timer B
+        let chained = match (self.tb.cr >> 5) & 3 {
+            2 => true,
+            3 => self.cnt_in,
+            _ => false,
+        };
+        if chained && self.tb.running() && self.tb.count() {
+            self.raise(TB);
+        }
+    }
+
+    fn tod_tick(&mut self) {
+        if self.tod_stopped {
+            return;
+        }
+        self.tod_div += 1;
+        if self.tod_div < if self.ta.cr & 0x80 != 0 { 5 } else { 6 } {
+            return;
+        }
+        self.tod_div = 0;
+        self.tod[0] = bcd_inc(self.tod[0]);
+        if self.tod[0] == 0x10 {
+            self.tod[0] = 0;
+            self.tod[1] = bcd_inc(self.tod[1]);
+            if self.tod[1] == 0x60 {
+                self.tod[1] = 0;
+                self.tod[2] = bcd_inc(self.tod[2]);
+                if self.tod[2] == 0x60 {
+                    self.tod[2] = 0;
+                    let pm = self.tod[3] & 0x80;
+                    self.tod[3] = match self.tod[3] & 0x1F {
+                        0x11 => 0x12 | (pm ^ 0x80),
+                        0x12 => 0x01 | pm,
+                        h => bcd_inc(h) | pm,
+                    };
+                }
+            }
+        }
+        if self.tod == self.alarm {
+            self.raise(ALARM);
+        }
+    }
+
+    // a cycle
+    fn step(&mut self) {
+        if self.irq_pending {
+            self.irq_pending = false;
+            self.irq_line = true;
+        }
+        self.ta.pulse = false;
+        self.tb.pulse = false;
+        let ta = self.ta.running() && self.ta.cr & 0x20 == 0 && self.ta.count();
+        if ta {
+            self.ta_underflow();
+        }
+        if self.tb.running() && self.tb.cr & 0x60 == 0 && self.tb.count() {
+            self.raise(TB);
+        }
+        self.tod_left -= 1;
+        if self.tod_left == 0 {
+            self.tod_left = self.tod_period;
+            self.tod_tick();
+        }
+    }
+
+    // cycles that can go by with counters counting and nothing else happening
+    fn quiet(&self) -> u64 {
+        if self.irq_pending || self.ta.pulse || self.tb.pulse {
+            return 0;
+        }
+        let mut q = self.tod_left - 1;
+        if self.ta.running() && self.ta.cr & 0x20 == 0 {
+            q = q.min(self.ta.counter as u64);
+        }
+        if self.tb.running() && self.tb.cr & 0x60 == 0 {
+            q = q.min(self.tb.counter as u64);
+        }
+        q
+    }
+
+    // register contents, no side effects
+    fn register(&self, reg: u16) -> u8 {
+        let tod = self.tod_latch.unwrap_or(self.tod);
+        match reg & 0x0F {
+            0x0 => self.port_a(),
+            0x1 => self.port_b(),
+            0x2 => self.ddra,
+            0x3 => self.ddrb,
+            0x4 => self.ta.counter as u8,
+            0x5 => (self.ta.counter >> 8) as u8,
+            0x6 => self.tb.counter as u8,
+            0x7 => (self.tb.counter >> 8) as u8,
+            r @ 0x8..=0xB => tod[(r - 0x8) as usize],
+            0xC => self.sdr,
+            0xD => self.icr | if self.icr & self.mask != 0 { 0x80 } else { 0 },
+            0xE => self.ta.cr,
+            0xF => self.tb.cr,
+            _ => unreachable!(),
+        }
+    }
+}
+
+// the pins, for whoever is on the other side: the tests, or a machine wiring them up
+#[cfg_attr(not(test), allow(dead_code))]
+impl Cia {
+    pub fn cnt(&self) -> bool {
+        if self.sp_output() { self.cnt_out } else { self.cnt_in }
+    }
+    pub fn sp(&self) -> bool {
+        if self.sp_output() { self.sp_out } else { self.sp_in }
+    }
+
+    pub fn set_port_a(&mut self, v: u8) {
+        self.pa_in = v;
+    }
+    pub fn set_port_b(&mut self, v: u8) {
+        self.pb_in = v;
+    }
+    pub fn set_sp(&mut self, level: bool) {
+        self.sp_in = level;
+    }
+    pub fn set_flag(&mut self, level: bool) {
+        if self.flag_in && !level {
+            self.raise(FLAG);
+        }
+        self.flag_in = level;
+    }
+    pub fn set_cnt(&mut self, level: bool) {
+        let rising = level && !self.cnt_in;
+        self.cnt_in = level;
+        if !rising {
+            return;
+        }
+        if !self.sp_output() {
+            self.sr = self.sr << 1 | self.sp_in as u8;
+            self.sr_bits += 1;
+            if self.sr_bits == 8 {
+                self.sr_bits = 0;
+                self.sdr = self.sr;
+                self.raise(SP);
+            }
+        }
+        let ta = self.ta.running() && self.ta.cr & 0x20 != 0 && self.ta.count();
+        if ta {
+            self.ta_underflow();
+        }
+        if self.tb.running() && (self.tb.cr >> 5) & 3 == 1 && self.tb.count() {
+            self.raise(TB);
+        }
+    }
+}
+
+impl Device for Cia {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        match reg & 0x0F {
+            0x8 => self.tod_latch = None,
+            0xB => self.tod_latch = Some(self.tod),
+            0xD => {
+                self.icr = 0;
+                self.irq_line = false;
+                self.irq_pending = false;
+            }
+            _ => {}
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 0x0F {
+            0x0 => self.pra = v,
+            0x1 => self.prb = v,
+            0x2 => self.ddra = v,
+            0x3 => self.ddrb = v,
+            0x4 => self.ta.latch = self.ta.latch & 0xFF00 | v as u16,
+            0x5 => self.ta.write_high(v),
+            0x6 => self.tb.latch = self.tb.latch & 0xFF00 | v as u16,
+            0x7 => self.tb.write_high(v),
+            r @ 0x8..=0xB => {
+                let i = (r - 0x8) as usize;
+                let v = v & [0x0F, 0x7F, 0x7F, 0x9F][i];
+                if self.tb.cr & 0x80 != 0 {
+                    self.alarm[i] = v;
+                } else {
+                    self.tod[i] = v;
+                    if i == 3 {
+                        self.tod_stopped = true;
+                    } else if i == 0 {
+                        self.tod_stopped = false;
+                    }
+                }
+            }
+            0xC => {
+                self.sdr = v;
+                if self.sp_output() {
+                    if self.sr_bits == 0 {
+                        self.sr = v;
+                        self.sr_bits = 16;
+                    } else {
+                        self.sdr_full = true;
+                    }
+                }
+            }
+            0xD => {
+                if v & 0x80 != 0 {
+                    self.mask |= v & 0x1F;
+                } else {
+                    self.mask &= !v;
+                }
+                self.raise(0);      // already pending flags, newly enabled
+            }
+            0xE => {
+                if (v ^ self.ta.cr) & 0x40 != 0 {
+                    self.sr_bits = 0;   // serial direction changed, start over
+                    self.sdr_full = false;
+                }
+                self.ta.write_cr(v);
+            }
+            0xF => self.tb.write_cr(v),
+            _ => unreachable!(),
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while cycles > 0 {
+            let q = self.quiet().min(cycles);
+            if q > 0 {
+                if self.ta.running() && self.ta.cr & 0x20 == 0 {
+                    self.ta.counter -= q as u16;
+                }
+                if self.tb.running() && self.tb.cr & 0x60 == 0 {
+                    self.tb.counter -= q as u16;
+                }
+                self.tod_left -= q;
+                cycles -= q;
+            } else {
+                self.step();
+                cycles -= 1;
+            }
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.irq_pending || self.ta.pulse || self.tb.pulse {
+            return Some(1);
+        }
+        let mut n = self.tod_left;
+        if self.ta.running() && self.ta.cr & 0x20 == 0 {
+            n = n.min(self.ta.counter as u64 + 1);
+        }
+        if self.tb.running() && self.tb.cr & 0x60 == 0 {
+            n = n.min(self.tb.counter as u64 + 1);
+        }
+        Some(n)
+    }
+    fn irq(&self) -> bool {
+        self.irq_line
+    }
+}
+
+// for the command line: clock=hz, mains=50|60, old
+pub fn create(opts: &[&str]) -> Result<Cia, String> {
+    let mut clock = 1_000_000;
+    let mut mains = 50;
+    let mut old = false;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("old"), None) => old = true,
+            (Some("clock"), Some(v)) => {
+                clock = v.parse::<u64>().ok().and_then(|v| if v > 0 { Some(v) } else { None })
+                         .ok_or(format!("bad clock {}", v))?;
+            }
+            (Some("mains"), Some("50")) => mains = 50,
+            (Some("mains"), Some("60")) => mains = 60,
+            _ => return Err(format!("unknown cia option {}", o)),
+        }
+    }
+    Ok(Cia::new(clock, mains, old))
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::cia::*;
+
+    #[test]
+    fn ports_mix_outputs_and_pins() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.write(0x2, 0x0F);
+        cia.write(0x0, 0x05);
+        cia.set_port_a(0xA0);
+        assert_eq!(cia.read(0x0), 0xA5);
+        cia.set_port_b(0x3C);
+        assert_eq!(cia.read(0x1), 0x3C);
+    }
+
+    #[test]
+    fn flag_falling_edge() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.set_flag(true);
+        assert_eq!(cia.peek(0xD), 0);
+        cia.set_flag(false);
+        assert_eq!(cia.read(0xD), FLAG);
+        assert_eq!(cia.read(0xD), 0);
+    }
+
+    #[test]
+    fn timer_a_counts_cnt_edges() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.write(0x4, 2);
+        cia.write(0x5, 0);
+        cia.write(0xE, 0x21);
+        for _ in 0..3 {
+            assert_eq!(cia.peek(0xD) & TA, 0);
+            cia.set_cnt(false);
+            cia.set_cnt(true);
+        }
+        assert_eq!(cia.peek(0xD) & TA, TA);
+        assert!(cia.cnt());
+    }
+
+    #[test]
+    fn serial_in_under_cnt() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        for i in (0..8).rev() {
+            cia.set_sp(0x96 >> i & 1 != 0);
+            assert_eq!(cia.sp(), 0x96 >> i & 1 != 0);
+            cia.set_cnt(false);
+            cia.set_cnt(true);
+        }
+        assert_eq!(cia.read(0xD), SP);
+        assert_eq!(cia.read(0xC), 0x96);
+    }
+
+    #[test]
+    fn timer_a_period_and_irq_timing() {
+        for &old in &[false, true] {
+            let mut cia = Cia::new(1_000_000, 50, old);
+            cia.write(0xD, 0x80 | TA);
+            cia.write(0x4, 10);
+            cia.write(0x5, 0);
+            cia.write(0xE, 0x11);
+            assert_eq!(cia.next_event(), Some(11));
+            cia.tick(10);
+            assert_eq!(cia.peek(0xD), 0);
+            cia.tick(1);                    // N + 1
+            assert_eq!(cia.peek(0xD), 0x80 | TA);
+            assert_eq!(cia.irq(), !old);
+            cia.tick(1);
+            assert!(cia.irq());             // the old one a cycle late
+            assert_eq!(cia.read(0xD), 0x80 | TA);
+            assert!(!cia.irq());
+            cia.tick(10);
+            assert_eq!(cia.peek(0xD), 0x80 | TA);   // reloaded and running
+        }
+    }
+
+    #[test]
+    fn one_shot_and_chained_timer_b() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.write(0xE, 0x08);
+        cia.write(0x4, 5);
+        cia.write(0x5, 0);                  // starts the one shot
+        cia.tick(6);
+        assert_eq!(cia.read(0xD), TA);
+        assert_eq!(cia.peek(0xE) & 0x01, 0);
+        assert_eq!(cia.read(0x4), 5);
+
+        cia.write(0x4, 1);
+        cia.write(0x6, 2);
+        cia.write(0x7, 0);
+        cia.write(0xF, 0x41);               // B counts A underflows
+        cia.write(0xE, 0x11);
+        cia.tick(5);
+        assert_eq!(cia.read(0xD), TA);
+        cia.tick(1);                        // a third A underflow, every 2 cycles
+        assert_eq!(cia.read(0xD), TA | TB);
+    }
+
+    #[test]
+    fn timer_outputs_on_port_b() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.write(0x4, 1);
+        cia.write(0x5, 0);
+        cia.write(0xE, 0x07);               // toggle on PB6
+        assert_eq!(cia.port_b() & 0x40, 0x40);
+        cia.tick(2);
+        assert_eq!(cia.port_b() & 0x40, 0);
+        cia.write(0xE, 0x03);               // pulse
+        cia.tick(2);
+        assert_eq!(cia.port_b() & 0x40, 0x40);
+        cia.tick(1);
+        assert_eq!(cia.port_b() & 0x40, 0);
+    }
+
+    #[test]
+    fn tod_latch_rollover_and_alarm() {
+        // 10 cycles a mains tick, 5 ticks a tenth
+        let mut cia = Cia::new(500, 50, false);
+        cia.write(0xE, 0x80);
+        cia.write(0xD, 0x80 | ALARM);
+        cia.write(0xF, 0x80);
+        cia.write(0xB, 0x12);
+        cia.write(0xA, 0);
+        cia.write(0x9, 0);
+        cia.write(0x8, 0);
+        cia.write(0xF, 0x00);
+        cia.write(0xB, 0x91);               // 11 PM, stops the clock
+        cia.write(0xA, 0x59);
+        cia.write(0x9, 0x59);
+        cia.tick(100);
+        assert_eq!(cia.read(0xB), 0x91);
+        cia.read(0x8);
+        cia.write(0x8, 0x09);               // and goes
+        cia.tick(49);
+        assert!(!cia.irq());
+        cia.tick(1);
+        assert!(cia.irq());
+        assert_eq!(cia.read(0xD), 0x80 | ALARM);
+        assert_eq!(cia.read(0xB), 0x12);    // midnight
+        cia.tick(600);
+        assert_eq!(cia.read(0xA), 0);
+        assert_eq!(cia.read(0x9), 0);       // latched at the hours read
+        assert_eq!(cia.read(0x8), 0);
+        assert_eq!(cia.read(0x8), 2);
+        assert_eq!(cia.read(0x9), 1);
+    }
+
+    #[test]
+    fn serial_out_under_timer_a() {
+        let mut cia = Cia::new(1_000_000, 50, false);
+        cia.write(0x4, 1);
+        cia.write(0x5, 0);
+        cia.write(0xE, 0x41);
+        cia.write(0xC, 0xA5);
+        let mut out = 0u8;
+        for _ in 0..8 {
+            cia.tick(2);
+            assert!(!cia.cnt());
+            out = out << 1 | cia.sp() as u8;
+            cia.tick(2);
+            assert!(cia.cnt());
+        }
+        assert_eq!(out, 0xA5);
+        assert_eq!(cia.read(0xD), TA | SP);
+    }
+}
@@ -0,0 +1,240 @@
+/*
+ * A console, the output half of a terminal.
+ *
+ *   0  data, write: a character out
+ *   1  status, read: bit 0 ready to send
+ *
+ * Line ends can be translated for the raw mode host terminal: with eol=cr a CR is a newline and
+ * LF is dropped, with eol=lf it is the other way around, raw leaves both alone. Backspace and
+ * delete erase the character before the cursor. ANSI escape sequences go through untouched,
+ * other control characters but bell and tab are dropped.
+ *
+ * A character takes delay cycles to go, not ready in the meantime, 0 by default. Output is
+ * buffered and flushed at newlines, or when it has been waiting a while in wall clock time.
+ */
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+use devices::Device;
+
+const ESC: u8 = 0x1B;
+const FLUSH: u64 = 20;          // ms
+const POLL: u64 = 10_000;       // cycles between looks at the wall clock, while something waits
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Eol {
+    Raw,
+    Cr,
+    Lf,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Escape {
+    None,
+    Esc,                // ESC seen
+    Csi,                // ESC [ seen, up to the final byte
+}
+
+pub struct Console {
+    out: Box<dyn Write>,
+    eol: Eol,
+    delay: u64,
+    busy: u64,          // cycles to ready
+    escape: Escape,
+    buffer: Vec<u8>,
+    since: Instant,     // first byte waiting in the buffer
+}
+
+impl Console {
+    pub fn new(out: Box<dyn Write>, eol: Eol, delay: u64) -> Console {
+        Console {
+            out,
+            eol,
+            delay,
+            busy: 0,
+            escape: Escape::None,
+            buffer: Vec::new(),
+            since: Instant::now(),
+        }
+    }
+
+    fn put(&mut self, v: u8) {
+        if self.escape != Escape::None {
+            // inside an escape sequence, as it is
+            self.escape = match (self.escape, v) {
+                (Escape::Esc, b'[') => Escape::Csi,
+                (Escape::Csi, 0x20..=0x3F) => Escape::Csi,
+                _ => Escape::None,
+            };
+            self.push(&[v]);
+            return;
+        }
+        match v {
+            ESC => {
+                self.escape = Escape::Esc;
+                self.push(&[v]);
+            }
+            b'\r' if self.eol == Eol::Cr => self.push(b"\r\n"),
+            b'\n' if self.eol == Eol::Cr => {}
+            b'\n' if self.eol == Eol::Lf => self.push(b"\r\n"),
+            b'\r' | b'\n' | 0x07 | b'\t' => self.push(&[v]),
+            0x08 | 0x7F => self.push(b"\x08 \x08"),
+            v if v < 0x20 => {}
+            v => self.push(&[v]),
+        }
+    }
+    fn push(&mut self, b: &[u8]) {
+        if self.buffer.is_empty() {
+            self.since = Instant::now();
+        }
+        self.buffer.extend_from_slice(b);
+        if b.contains(&b'\n') {
+            self.flush();
+        }
+    }
+    fn flush(&mut self) {
+        // the host may well be gone, nothing to do about it
+        let _ = self.out.write_all(&self.buffer);
+        let _ = self.out.flush();
+        self.buffer.clear();
+    }
+}
+
+impl Device for Console {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.peek(reg)
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        if reg & 1 == 0 {
+            self.put(v);
+            self.busy = self.delay;
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        if reg & 1 == 0 { 0 } else { (self.busy == 0) as u8 }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.busy = self.busy.saturating_sub(cycles);
+        if !self.buffer.is_empty() && self.since.elapsed() >= Duration::from_millis(FLUSH) {
+            self.flush();
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        match (self.busy, self.buffer.is_empty()) {
+            (0, true) => None,
+            (0, false) => Some(POLL),
+            (b, true) => Some(b),
+            (b, false) => Some(b.min(POLL)),
+        }
+    }
+}
+
+impl Drop for Console {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+// for the command line: eol=raw|cr|lf, delay=cycles
+pub fn create(opts: &[&str]) -> Result<Console, String> {
+    let mut eol = Eol::Raw;
+    let mut delay = 0;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("eol"), Some("raw")) => eol = Eol::Raw,
+            (Some("eol"), Some("cr")) => eol = Eol::Cr,
+            (Some("eol"), Some("lf")) => eol = Eol::Lf,
+            (Some("delay"), Some(v)) => delay = v.parse::<u64>().map_err(|_| format!("bad delay {}", v))?,
+            _ => return Err(format!("unknown console option {}", o)),
+        }
+    }
+    Ok(Console::new(Box::new(::std::io::stdout()), eol, delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use devices::Device;
+    use devices::console::*;
+
+    #[derive(Clone, Default)]
+    struct Tape(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Tape {
+        fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(b);
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // what comes out of the console for the bytes written, all flushed
+    fn typed(eol: Eol, s: &[u8]) -> Vec<u8> {
+        let tape = Tape::default();
+        let mut con = Console::new(Box::new(tape.clone()), eol, 0);
+        for &v in s {
+            con.write(0, v);
+        }
+        drop(con);
+        let out = tape.0.borrow().clone();
+        out
+    }
+
+    #[test]
+    fn line_ends() {
+        assert_eq!(typed(Eol::Raw, b"a\rb\nc\r\n"), b"a\rb\nc\r\n");
+        assert_eq!(typed(Eol::Cr, b"a\rb\nc\r\n"), b"a\r\nbc\r\n");
+        assert_eq!(typed(Eol::Lf, b"a\rb\nc\r\n"), b"a\rb\r\nc\r\r\n");
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(typed(Eol::Raw, b"ab\x08c\x7Fd"), b"ab\x08 \x08c\x08 \x08d");
+        assert_eq!(typed(Eol::Raw, b"\x07\t\x00\x01\x0C!"), b"\x07\t!");
+    }
+
+    #[test]
+    fn escape_sequences_go_through() {
+        assert_eq!(typed(Eol::Cr, b"\x1B[2J\x1B[1;31mred\x1B[0m"), b"\x1B[2J\x1B[1;31mred\x1B[0m");
+        assert_eq!(typed(Eol::Raw, b"\x1Bc\x01"), b"\x1Bc");      // only the sequence, not what follows
+    }
+
+    #[test]
+    fn ready_after_the_delay() {
+        let mut con = Console::new(Box::new(Tape::default()), Eol::Raw, 100);
+        assert_eq!(con.read(1), 0x01);
+        assert_eq!(con.next_event(), None);
+        con.write(0, b'\n');
+        assert_eq!(con.read(1), 0x00);
+        assert_eq!(con.next_event(), Some(100));
+        con.tick(99);
+        assert_eq!(con.read(1), 0x00);
+        con.tick(1);
+        assert_eq!(con.read(1), 0x01);
+        assert_eq!(con.next_event(), None);
+    }
+
+    #[test]
+    fn flushed_at_newline_or_after_a_while() {
+        let tape = Tape::default();
+        let mut con = Console::new(Box::new(tape.clone()), Eol::Raw, 0);
+        con.write(0, b'o');
+        con.write(0, b'k');
+        assert!(tape.0.borrow().is_empty());
+        con.write(0, b'\n');
+        assert_eq!(*tape.0.borrow(), b"ok\n");
+        con.write(0, b'>');
+        assert_eq!(con.next_event(), Some(POLL));
+        con.tick(POLL);
+        assert_eq!(*tape.0.borrow(), b"ok\n");
+        ::std::thread::sleep(Duration::from_millis(FLUSH + 5));
+        con.tick(POLL);
+        assert_eq!(*tape.0.borrow(), b"ok\n>");
+        assert_eq!(con.next_event(), None);
+    }
+}
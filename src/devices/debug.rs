@@ -0,0 +1,120 @@
+/*
+ * A debug port, for test programs run unattended: they can report, check themselves and end
+ * the run with a status, like any program on the host.
+ *
+ *   0    exit, write: stop the run, the value is the exit status
+ *   1    hex, write: print the value in hex on a line of its own
+ *   2-5  cycle, read: the cpu cycle count, little endian. Reading 2 latches all four bytes
+ *   6    assert, write: stop the run with an assertion failure, the value tells which
+ *
+ * Exit and assertions stop the cpu with a bus fault, so whoever runs it knows the PC and the
+ * cycle. The count is kept from the bus clock, which is the cpu's own when the bus starts with it.
+ */
+
+use std::io::Write;
+use cpu::BusFault;
+use devices::Device;
+
+pub struct Debug {
+    out: Box<dyn Write>,
+    cycle: u64,
+    latch: u32,
+    fault: Option<BusFault>,
+}
+
+impl Debug {
+    pub fn new(out: Box<dyn Write>) -> Debug {
+        Debug { out, cycle: 0, latch: 0, fault: None }
+    }
+}
+
+impl Device for Debug {
+    fn read(&mut self, reg: u16) -> u8 {
+        if reg == 2 {
+            self.latch = self.cycle as u32;
+        }
+        self.peek(reg)
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => self.fault = Some(BusFault::Exit(v)),
+            1 => {
+                let _ = write!(self.out, "{:02x}\r\n", v);
+                let _ = self.out.flush();
+            }
+            6 => self.fault = Some(BusFault::Assert(v)),
+            _ => {}
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        match reg {
+            2 => self.cycle as u8,
+            3..=5 => (self.latch >> (8 * (reg - 2))) as u8,
+            _ => 0,
+        }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.cycle += cycles;
+    }
+    fn next_event(&self) -> Option<u64> {
+        None
+    }
+    fn fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use cpu::BusFault;
+    use devices::Device;
+    use devices::debug::*;
+
+    #[derive(Clone, Default)]
+    struct Tape(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Tape {
+        fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(b);
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn exit_and_assert_are_faults() {
+        let mut d = Debug::new(Box::new(Tape::default()));
+        assert_eq!(d.fault(), None);
+        d.write(0, 3);
+        assert_eq!(d.fault(), Some(BusFault::Exit(3)));
+        assert_eq!(d.fault(), None);
+        d.write(6, 0x42);
+        assert_eq!(d.fault(), Some(BusFault::Assert(0x42)));
+    }
+
+    #[test]
+    fn hex_on_a_line_of_its_own() {
+        let tape = Tape::default();
+        let mut d = Debug::new(Box::new(tape.clone()));
+        d.write(1, 0x0A);
+        d.write(1, 0xFF);
+        assert_eq!(*tape.0.borrow(), b"0a\r\nff\r\n");
+        assert_eq!(d.fault(), None);
+    }
+
+    #[test]
+    fn cycles_latched_on_the_low_byte() {
+        let mut d = Debug::new(Box::new(Tape::default()));
+        d.tick(0x12345678);
+        assert_eq!(d.read(2), 0x78);
+        d.tick(0x01010101);
+        assert_eq!((d.read(3), d.read(4), d.read(5)), (0x56, 0x34, 0x12));
+        assert_eq!(d.read(2), 0x79);
+        assert_eq!((d.read(3), d.read(4), d.read(5)), (0x57, 0x35, 0x13));
+    }
+}
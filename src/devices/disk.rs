@@ -0,0 +1,281 @@
+/*
+ * A disk controller for a host image file: 512 byte sectors, moved to and from memory by DMA.
+ *
+ *   0-1  sector number, little endian
+ *   2-3  buffer address, little endian
+ *   4    command, write: 1 read the sector into the buffer, 2 write the buffer into the sector
+ *        status, read: bit 0 error, bit 6 done, bit 7 busy. Reading it acknowledges done
+ *   5    control: bit 0 interrupt when done
+ *   6-7  size of the image in sectors, read only
+ *
+ * A command waits delay cycles (seek time, 0 by default), then the sector goes in a burst of 512
+ * cycles stolen from the cpu, then done is set. Sector and buffer are taken when the command is
+ * written; commands while busy are ignored. Sectors past the end of the image, writes into a read
+ * only image, unknown commands and host I/O errors end at once with error and done.
+ * A short last sector in the image doesn't count, nor do sectors past 65535.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use devices::{Device, Dma};
+
+pub const SECTOR: usize = 512;
+
+const ERROR: u8 = 0x01;
+const DONE: u8 = 0x40;
+const BUSY: u8 = 0x80;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    Idle,
+    Seek,
+    Transfer,
+}
+
+pub struct Disk {
+    image: File,
+    readonly: bool,
+    sectors: u16,
+    delay: u64,
+    sector: u16,
+    address: u16,
+    command: u8,                // the one going on
+    at: (u16, u16),             // sector and buffer of the command going on
+    status: u8,
+    irq_enable: bool,
+    phase: Phase,
+    busy: u64,                  // cycles to the end of the phase
+    dma: Option<Dma>,
+}
+
+impl Disk {
+    pub fn new(image: File, readonly: bool, delay: u64) -> Result<Disk, String> {
+        let len = image.metadata().map_err(|e| e.to_string())?.len();
+        Ok(Disk {
+            image,
+            readonly,
+            sectors: (len / SECTOR as u64).min(0xFFFF) as u16,
+            delay,
+            sector: 0,
+            address: 0,
+            command: 0,
+            at: (0, 0),
+            status: 0,
+            irq_enable: false,
+            phase: Phase::Idle,
+            busy: 0,
+            dma: None,
+        })
+    }
+
+    fn command(&mut self, v: u8) {
+        if self.phase != Phase::Idle {
+            return;
+        }
+        self.command = v;
+        self.at = (self.sector, self.address);
+        self.status = BUSY;
+        let bad = match v {
+            1 => self.sector >= self.sectors,
+            2 => self.sector >= self.sectors || self.readonly,
+            _ => true,
+        };
+        if bad {
+            self.status = ERROR | DONE;
+        } else if self.delay > 0 {
+            self.phase = Phase::Seek;
+            self.busy = self.delay;
+        } else {
+            self.transfer();
+        }
+    }
+
+    // the head is there, hand the bus the sector or ask it for the buffer
+    fn transfer(&mut self) {
+        let (sector, address) = self.at;
+        if self.command == 1 {
+            let mut data = vec![0u8; SECTOR];
+            let read = self.image.seek(SeekFrom::Start(sector as u64 * SECTOR as u64))
+                .and_then(|_| self.image.read_exact(&mut data));
+            if read.is_err() {
+                self.finish(true);
+                return;
+            }
+            self.dma = Some(Dma::Write(address, data));
+        } else {
+            self.dma = Some(Dma::Read(address, SECTOR));
+        }
+        self.phase = Phase::Transfer;
+        self.busy = SECTOR as u64;
+    }
+
+    fn finish(&mut self, error: bool) {
+        self.phase = Phase::Idle;
+        self.status = DONE | if error { ERROR } else { 0 };
+    }
+
+    fn register(&self, reg: u16) -> u8 {
+        match reg {
+            0 => self.sector as u8,
+            1 => (self.sector >> 8) as u8,
+            2 => self.address as u8,
+            3 => (self.address >> 8) as u8,
+            4 => self.status,
+            5 => self.irq_enable as u8,
+            6 => self.sectors as u8,
+            _ => (self.sectors >> 8) as u8,
+        }
+    }
+}
+
+impl Device for Disk {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        if reg == 4 {
+            self.status &= !DONE;
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0 => self.sector = self.sector & 0xFF00 | v as u16,
+            1 => self.sector = self.sector & 0x00FF | (v as u16) << 8,
+            2 => self.address = self.address & 0xFF00 | v as u16,
+            3 => self.address = self.address & 0x00FF | (v as u16) << 8,
+            4 => self.command(v),
+            5 => self.irq_enable = v & 0x01 != 0,
+            _ => {}
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while self.phase != Phase::Idle && cycles >= self.busy {
+            cycles -= self.busy;
+            if self.phase == Phase::Seek {
+                self.transfer();
+            } else {
+                self.finish(false);
+            }
+        }
+        if self.phase != Phase::Idle {
+            self.busy -= cycles;
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.phase != Phase::Idle { Some(self.busy) } else { None }
+    }
+    fn irq(&self) -> bool {
+        self.irq_enable && self.status & DONE != 0
+    }
+    fn dma(&mut self) -> Option<Dma> {
+        self.dma.take()
+    }
+    fn dma_read(&mut self, data: Vec<u8>) {
+        let sector = self.at.0;
+        let written = self.image.seek(SeekFrom::Start(sector as u64 * SECTOR as u64))
+            .and_then(|_| self.image.write_all(&data))
+            .and_then(|_| self.image.flush());
+        if written.is_err() {
+            self.finish(true);
+        }
+    }
+}
+
+// for the command line: file=image (required), ro, delay=cycles
+pub fn create(opts: &[&str]) -> Result<Disk, String> {
+    let mut file = None;
+    let mut readonly = false;
+    let mut delay = 0;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("file"), Some(f)) => file = Some(f),
+            (Some("ro"), None) => readonly = true,
+            (Some("delay"), Some(v)) => delay = v.parse::<u64>().map_err(|_| format!("bad delay {}", v))?,
+            _ => return Err(format!("unknown disk option {}", o)),
+        }
+    }
+    let f = file.ok_or("disk needs file=image".to_string())?;
+    let image = OpenOptions::new().read(true).write(!readonly).open(f).map_err(|e| format!("{}: {}", f, e))?;
+    Disk::new(image, readonly, delay)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use devices::{Device, Dma};
+    use devices::disk::*;
+
+    // an image of two sectors, 0x11 and 0x22 all over
+    fn image(name: &str) -> (String, File) {
+        // tests run in parallel, and maybe more runs at once
+        let name = format!("hell65-{}-{}", ::std::process::id(), name);
+        let path = ::std::env::temp_dir().join(name).to_string_lossy().into_owned();
+        let mut data = vec![0x11; SECTOR];
+        data.extend(vec![0x22; SECTOR + 100]);      // and a short one, not counted
+        fs::write(&path, data).unwrap();
+        let f = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        (path, f)
+    }
+
+    #[test]
+    fn reads_after_the_seek() {
+        let (path, f) = image("disk-read.img");
+        let mut disk = Disk::new(f, false, 100).unwrap();
+        assert_eq!(disk.read(6), 2);
+        disk.write(0, 1);
+        disk.write(3, 0x30);
+        disk.write(5, 1);
+        disk.write(4, 1);
+        assert_eq!(disk.read(4), 0x80);
+        assert_eq!(disk.next_event(), Some(100));
+        disk.tick(100);
+        match disk.dma() {
+            Some(Dma::Write(0x3000, ref data)) => assert!(data.len() == SECTOR && data.iter().all(|&b| b == 0x22)),
+            _ => panic!("no sector"),
+        }
+        disk.tick(511);
+        assert!(!disk.irq());
+        disk.tick(1);
+        assert!(disk.irq());
+        assert_eq!(disk.read(4), 0x40);
+        assert!(!disk.irq());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_the_buffer() {
+        let (path, f) = image("disk-write.img");
+        let mut disk = Disk::new(f, false, 0).unwrap();
+        disk.write(2, 0x10);
+        disk.write(4, 2);
+        match disk.dma() {
+            Some(Dma::Read(0x0010, SECTOR)) => disk.dma_read(vec![0x33; SECTOR]),
+            _ => panic!("no buffer asked"),
+        }
+        disk.tick(SECTOR as u64);
+        assert_eq!(disk.read(4), 0x40);
+        let data = fs::read(&path).unwrap();
+        assert!(data[..SECTOR].iter().all(|&b| b == 0x33));
+        assert_eq!(data[SECTOR], 0x22);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn errors_end_at_once() {
+        let (path, f) = image("disk-errors.img");
+        let mut disk = Disk::new(f, true, 10).unwrap();
+        disk.write(4, 2);                           // read only
+        assert_eq!(disk.read(4), 0x41);
+        disk.write(0, 2);
+        disk.write(4, 1);                           // the short sector
+        assert_eq!(disk.read(4), 0x41);
+        disk.write(4, 3);
+        assert_eq!(disk.read(4), 0x41);
+        assert_eq!(disk.next_event(), None);
+        assert!(disk.dma().is_none());
+        fs::remove_file(path).unwrap();
+    }
+}
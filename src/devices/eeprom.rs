@@ -0,0 +1,94 @@
+/*
+ * Microchip 24LC256, 32KB I2C EEPROM, kept in a host file.
+ *
+ * Address 50-57 (A2-A0 pins). A write sends two address bytes, high first, then data into the
+ * 64 byte page buffer, wrapping around inside the page. STOP starts the write cycle, 5ms (at
+ * 1MHz): the chip doesn't acknowledge its address until it is over, for acknowledge polling.
+ * Only then the page lands in the file. A read goes on from the address counter, set by a write
+ * with just the address bytes, wrapping around at the end of the memory.
+ *
+ * A new file, or a short one, is filled up to 32KB with FF like a blank chip.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use devices::i2c::I2cDevice;
+
+const SIZE: usize = 32768;
+const PAGE: usize = 64;
+const WRITE_CYCLE: u64 = 5000;
+
+pub struct Eeprom {
+    file: File,
+    m: Vec<u8>,
+    address: u8,
+    counter: usize,
+    received: usize,    // bytes of the write so far, address included
+    page: Vec<(usize, u8)>,
+    busy: u64,          // cycles to the end of the write cycle
+}
+
+impl Eeprom {
+    pub fn open(file: &str, pins: u8) -> Result<Eeprom, String> {
+        let mut f = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(file)
+                            .map_err(|e| format!("{}: {}", file, e))?;
+        let mut m = Vec::new();
+        f.read_to_end(&mut m).map_err(|e| format!("{}: {}", file, e))?;
+        if m.len() < SIZE {
+            m.resize(SIZE, 0xFF);
+            f.seek(SeekFrom::Start(0)).and_then(|_| f.write_all(&m)).map_err(|e| format!("{}: {}", file, e))?;
+        }
+        Ok(Eeprom { file: f, m, address: 0x50 | pins & 7, counter: 0, received: 0, page: Vec::new(), busy: 0 })
+    }
+
+    fn commit(&mut self) {
+        for &(a, v) in self.page.iter() {
+            self.m[a] = v;
+        }
+        let start = self.page[0].0 & !(PAGE - 1);
+        // the host may be full, the chip can't say
+        let _ = self.file.seek(SeekFrom::Start(start as u64))
+            .and_then(|_| self.file.write_all(&self.m[start..start + PAGE]))
+            .and_then(|_| self.file.flush());
+        self.page.clear();
+        self.busy = WRITE_CYCLE;
+    }
+}
+
+impl I2cDevice for Eeprom {
+    fn address(&self) -> u8 {
+        self.address
+    }
+    fn start(&mut self, _read: bool) -> bool {
+        self.received = 0;
+        self.page.clear();
+        self.busy == 0
+    }
+    fn write(&mut self, v: u8) -> bool {
+        match self.received {
+            0 => self.counter = (v as usize & 0x7F) << 8 | self.counter & 0xFF,
+            1 => self.counter = self.counter & 0x7F00 | v as usize,
+            _ => {
+                let a = self.counter;
+                self.page.retain(|&(p, _)| p != a);
+                self.page.push((a, v));
+                self.counter = a & !(PAGE - 1) | (a + 1) & (PAGE - 1);
+            }
+        }
+        self.received += 1;
+        true
+    }
+    fn read(&mut self) -> u8 {
+        let v = self.m[self.counter];
+        self.counter = (self.counter + 1) % SIZE;
+        v
+    }
+    fn stop(&mut self) {
+        if !self.page.is_empty() {
+            self.commit();
+        }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.busy = self.busy.saturating_sub(cycles);
+    }
+}
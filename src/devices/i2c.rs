@@ -0,0 +1,281 @@
+/*
+ * I2C, bit level: two open drain lines, SCL and SDA, pulled up. The master bangs them through
+ * port pins, the slaves here answer as they would, bit by bit.
+ *
+ * START (SDA falls with SCL high), the address byte with R/W in bit 0, then bytes, each followed
+ * by an acknowledge bit driven low by the receiver on the ninth clock. STOP (SDA rises with SCL
+ * high) ends it, a repeated START starts over. Data moves while SCL is low: bits are sampled on the
+ * rising edge, slaves change SDA on the falling one. A master reading says it wants no more with
+ * a NACK. No clock stretching, no arbitration, no general call.
+ *
+ * Slaves see bytes, not bits: the bus does the shifting and the acknowledges.
+ */
+
+// a slave on the bus
+pub trait I2cDevice {
+    // 7 bit address
+    fn address(&self) -> u8;
+    // addressed after a START, for a read or a write. False is a NACK
+    fn start(&mut self, _read: bool) -> bool { true }
+    // a byte from the master. False is a NACK
+    fn write(&mut self, v: u8) -> bool;
+    // a byte for the master
+    fn read(&mut self) -> u8;
+    fn stop(&mut self) {}
+    // time goes on, for slaves doing something by themselves
+    fn tick(&mut self, _cycles: u64) {}
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Idle,               // waiting for a START, nobody is talking to us
+    Address,
+    Write,
+    Read,
+}
+
+pub struct I2c {
+    slaves: Vec<Box<dyn I2cDevice>>,
+    scl: bool,
+    master: bool,       // SDA as the master drives it, true released
+    slave: bool,        // and as the slaves do
+    state: State,
+    bit: u8,            // sampled in this byte
+    shift: u8,
+    selected: Option<usize>,
+    read: bool,         // the address byte said so
+    nack: bool,         // the master doesn't want more
+}
+
+impl I2c {
+    pub fn new() -> I2c {
+        I2c {
+            slaves: Vec::new(),
+            scl: true, master: true, slave: true,
+            state: State::Idle, bit: 0, shift: 0, selected: None, read: false, nack: false,
+        }
+    }
+
+    pub fn attach(&mut self, dev: Box<dyn I2cDevice>) {
+        self.slaves.push(dev);
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        for s in self.slaves.iter_mut() {
+            s.tick(cycles);
+        }
+    }
+
+    // SDA as everybody sees it
+    pub fn sda(&self) -> bool {
+        self.master && self.slave
+    }
+
+    // the lines as the master drives them, true released. SDA is back
+    pub fn pins(&mut self, scl: bool, sda: bool) -> bool {
+        let before = self.sda();
+        self.master = sda;
+        if self.scl && scl && before != self.sda() {
+            if self.sda() { self.stop() } else { self.start() }
+        }
+        if scl && !self.scl {
+            self.rising();
+        } else if !scl && self.scl {
+            self.falling();
+        }
+        self.scl = scl;
+        self.sda()
+    }
+
+    fn start(&mut self) {
+        self.state = State::Address;
+        self.bit = 0;
+        self.shift = 0;
+        self.slave = true;
+        self.selected = None;
+    }
+
+    fn stop(&mut self) {
+        if let Some(s) = self.selected.take() {
+            self.slaves[s].stop();
+        }
+        self.state = State::Idle;
+        self.slave = true;
+    }
+
+    // bits are counted as they are sampled: 8 is the acknowledge clock, 9 past it
+    fn rising(&mut self) {
+        let sda = self.sda();
+        match self.state {
+            State::Idle => return,
+            State::Address | State::Write if self.bit < 8 => self.shift = self.shift << 1 | sda as u8,
+            State::Read if self.bit == 8 => self.nack = sda,
+            _ => {}
+        }
+        self.bit += 1;
+    }
+
+    fn falling(&mut self) {
+        match (self.state, self.bit) {
+            (State::Address, 8) | (State::Write, 8) => {
+                let ack = if self.state == State::Address {
+                    let address = self.shift >> 1;
+                    self.read = self.shift & 1 != 0;
+                    self.selected = self.slaves.iter().position(|s| s.address() == address);
+                    match self.selected {
+                        Some(s) => self.slaves[s].start(self.read),
+                        None => false,
+                    }
+                } else {
+                    let (s, v) = (self.selected.unwrap(), self.shift);
+                    self.slaves[s].write(v)
+                };
+                self.slave = !ack;
+            }
+            (State::Address, 9) | (State::Write, 9) => {
+                // acknowledge clock over
+                let acked = !self.slave;
+                self.slave = true;
+                self.bit = 0;
+                self.shift = 0;
+                if self.state == State::Address {
+                    self.state = match self.selected {
+                        Some(s) if acked && self.read => {
+                            self.load(s);   // the first bit goes out right away
+                            State::Read
+                        }
+                        Some(_) if acked => State::Write,
+                        _ => State::Idle,
+                    };
+                }
+            }
+            (State::Read, b) if b < 8 => self.slave = self.shift & 0x80 >> b != 0,
+            (State::Read, 8) => self.slave = true,      // the master acknowledges
+            (State::Read, _) => {
+                // acknowledge clock over, next byte unless NACKed
+                self.bit = 0;
+                if self.nack {
+                    self.state = State::Idle;
+                    self.slave = true;
+                } else {
+                    let s = self.selected.unwrap();
+                    self.load(s);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn load(&mut self, s: usize) {
+        self.shift = self.slaves[s].read();
+        self.slave = self.shift & 0x80 != 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use devices::eeprom::Eeprom;
+    use devices::rtc::Rtc;
+    use devices::i2c::*;
+
+    // the master side, banged a bit at a time
+    struct Master(I2c);
+
+    impl Master {
+        fn start(&mut self) {
+            self.0.pins(true, true);
+            self.0.pins(true, false);
+            self.0.pins(false, false);
+        }
+        fn stop(&mut self) {
+            self.0.pins(false, false);
+            self.0.pins(true, false);
+            self.0.pins(true, true);
+        }
+        // true if acknowledged
+        fn write(&mut self, v: u8) -> bool {
+            for i in (0..8).rev() {
+                let bit = v >> i & 1 != 0;
+                self.0.pins(false, bit);
+                self.0.pins(true, bit);
+                self.0.pins(false, bit);
+            }
+            self.0.pins(false, true);
+            let ack = !self.0.pins(true, true);
+            self.0.pins(false, true);
+            ack
+        }
+        fn read(&mut self, more: bool) -> u8 {
+            let mut v = 0;
+            for _ in 0..8 {
+                v = v << 1 | self.0.pins(true, true) as u8;
+                self.0.pins(false, true);
+            }
+            self.0.pins(false, !more);
+            self.0.pins(true, !more);
+            self.0.pins(false, !more);
+            self.0.pins(false, true);
+            v
+        }
+    }
+
+    #[test]
+    fn nobody_there() {
+        let mut m = Master(I2c::new());
+        m.0.attach(Box::new(Rtc::new(0)));
+        m.start();
+        assert!(!m.write(0xA0));
+        assert!(!m.write(0x00));            // not listening
+        m.stop();
+        assert!(m.0.sda());
+    }
+
+    #[test]
+    fn eeprom_page_write_and_random_read() {
+        let name = format!("hell65-{}-eeprom.bin", ::std::process::id());     // more runs at once
+        let path = ::std::env::temp_dir().join(name).to_string_lossy().into_owned();
+        let _ = fs::remove_file(&path);
+        let mut m = Master(I2c::new());
+        m.0.attach(Box::new(Eeprom::open(&path, 1).unwrap()));
+        m.start();
+        for &b in &[0xA2, 0x01, 0x3E, 1, 2, 3, 4] {
+            assert!(m.write(b));
+        }
+        m.stop();
+        m.start();
+        assert!(!m.write(0xA2));            // writing, acknowledge polling
+        m.stop();
+        m.0.tick(5000);
+        m.start();
+        assert!(m.write(0xA2) && m.write(0x01) && m.write(0x00));
+        m.start();                          // repeated
+        assert!(m.write(0xA3));
+        assert_eq!(m.read(true), 3);        // wrapped in the page
+        assert_eq!(m.read(true), 4);
+        assert_eq!(m.read(false), 0xFF);
+        m.stop();
+        let data = fs::read(&path).unwrap();
+        assert_eq!(data.len(), 32768);
+        assert_eq!(data[0x13E..0x140], [1, 2]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn rtc_time_and_ram() {
+        let mut m = Master(I2c::new());
+        m.0.attach(Box::new(Rtc::new(0)));
+        m.start();
+        assert!(m.write(0xD0) && m.write(0x3F) && m.write(0x42));
+        m.stop();
+        m.0.tick(1_000_000);
+        m.start();
+        assert!(m.write(0xD0) && m.write(0x3F));
+        m.start();
+        assert!(m.write(0xD1));
+        assert_eq!(m.read(true), 0x42);     // 3F, then around to 0
+        let t: Vec<u8> = (0..7).map(|i| m.read(i < 6)).collect();
+        m.stop();
+        assert_eq!(t, [0x01, 0x00, 0x00, 0x05, 0x01, 0x01, 0x70]);
+    }
+}
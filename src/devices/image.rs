@@ -0,0 +1,178 @@
+/*
+ * Frames to image files, for the video devices to show on a machine with no display.
+ *
+ * PPM (binary, P6) or PNG, by the extension of the file name. The PNG is 8 bit RGB, not
+ * compressed: deflate has stored blocks for that, so it needs nothing but the checksums. Files are
+ * bigger, any viewer or image diff reads them the same.
+ */
+
+use std::fs::File;
+use std::io::{self, Write};
+
+// width x height pixels, rgb has 3 bytes each, row after row
+pub fn save(name: &str, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let data = if name.to_lowercase().ends_with(".png") {
+        png(width, height, rgb)
+    } else {
+        ppm(width, height, rgb)
+    };
+    let mut f = File::create(name)?;
+    f.write_all(&data)
+}
+
+// a name for the nth picture: # in the pattern becomes the number, else the same file every time
+pub fn numbered(pattern: &str, n: u32) -> String {
+    pattern.replace('#', &format!("{:06}", n))
+}
+
+pub fn ppm(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut v = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    v.extend_from_slice(&rgb[..width * height * 3]);
+    v
+}
+
+pub fn png(width: usize, height: usize, rgb: &[u8]) -> Vec<u8> {
+    let mut v = vec![0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    let mut header = Vec::new();
+    header.extend_from_slice(&be32(width as u32));
+    header.extend_from_slice(&be32(height as u32));
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);     // 8 bits, RGB, deflate, filters, no interlace
+    chunk(&mut v, b"IHDR", &header);
+    // each row starts with its filter, none
+    let mut raw = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb[..width * height * 3].chunks(width * 3) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut v, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut v, b"IEND", &[]);
+    v
+}
+
+fn be32(n: u32) -> [u8; 4] {
+    [(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn chunk(v: &mut Vec<u8>, kind: &[u8], data: &[u8]) {
+    v.extend_from_slice(&be32(data.len() as u32));
+    let start = v.len();
+    v.extend_from_slice(kind);
+    v.extend_from_slice(data);
+    let crc = crc32(&v[start..]);
+    v.extend_from_slice(&be32(crc));
+}
+
+// zlib stream of stored deflate blocks, 65535 bytes at most each
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut v = vec![0x78, 0x01];
+    let mut blocks = data.chunks(65535).peekable();
+    if blocks.peek().is_none() {
+        v.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(b) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = b.len() as u16;
+        v.push(last as u8);
+        v.extend_from_slice(&[len as u8, (len >> 8) as u8, !len as u8, (!len >> 8) as u8]);
+        v.extend_from_slice(b);
+    }
+    v.extend_from_slice(&be32(adler32(data)));
+    v
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &d in data {
+        a = (a + d as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::image::*;
+
+    // the chunks of a PNG, their CRCs checked
+    fn chunks(png: &[u8]) -> Vec<(String, Vec<u8>)> {
+        let mut v = Vec::new();
+        let mut at = 8;
+        while at < png.len() {
+            let len = (png[at] as usize) << 24 | (png[at + 1] as usize) << 16 | (png[at + 2] as usize) << 8 | png[at + 3] as usize;
+            let body = &png[at + 4..at + 8 + len];
+            assert_eq!(png[at + 8 + len..at + 12 + len], be32(crc32(body)));
+            v.push((String::from_utf8_lossy(&body[..4]).into_owned(), body[4..].to_vec()));
+            at += 12 + len;
+        }
+        v
+    }
+
+    // the data of a zlib stream of stored blocks, and how many blocks
+    fn unstore(z: &[u8]) -> (Vec<u8>, usize) {
+        assert_eq!(((z[0] as u16) << 8 | z[1] as u16) % 31, 0);    // the header check
+        assert_eq!(z[0], 0x78);
+        let (mut data, mut blocks, mut at) = (Vec::new(), 0, 2);
+        loop {
+            let last = z[at] & 1 != 0;
+            let len = z[at + 1] as usize | (z[at + 2] as usize) << 8;
+            assert_eq!(len as u16, !(z[at + 3] as u16 | (z[at + 4] as u16) << 8));
+            data.extend_from_slice(&z[at + 5..at + 5 + len]);
+            blocks += 1;
+            at += 5 + len;
+            if last {
+                break;
+            }
+        }
+        assert_eq!(z[at..], be32(adler32(&data)));
+        (data, blocks)
+    }
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn a_png() {
+        let rgb: Vec<u8> = (0..2 * 3 * 3).map(|i| i as u8).collect();
+        let png = png(2, 3, &rgb);
+        assert_eq!(png[..8], [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+        let c = chunks(&png);
+        assert_eq!(c.iter().map(|c| c.0.as_str()).collect::<Vec<_>>(), ["IHDR", "IDAT", "IEND"]);
+        assert_eq!(c[0].1, [0, 0, 0, 2, 0, 0, 0, 3, 8, 2, 0, 0, 0]);
+        let (raw, blocks) = unstore(&c[1].1);
+        assert_eq!(blocks, 1);
+        assert_eq!(raw[..7], [0, 0, 1, 2, 3, 4, 5]);
+        assert_eq!(raw[7..14], [0, 6, 7, 8, 9, 10, 11]);
+        assert_eq!(raw.len(), 3 * 7);
+    }
+
+    #[test]
+    fn stored_blocks_of_64k_at_most() {
+        let data: Vec<u8> = (0..65535 * 2 + 10).map(|i| (i * 7) as u8).collect();
+        let (back, blocks) = unstore(&zlib_stored(&data));
+        assert_eq!(blocks, 3);
+        assert!(back == data);
+        assert_eq!(unstore(&zlib_stored(&[])), (Vec::new(), 1));
+    }
+
+    #[test]
+    fn ppm_and_names() {
+        assert_eq!(ppm(1, 1, &[1, 2, 3, 4]), b"P6\n1 1\n255\n\x01\x02\x03");
+        assert_eq!(numbered("shot#.png", 42), "shot000042.png");
+        assert_eq!(numbered("shot.png", 42), "shot.png");
+    }
+}
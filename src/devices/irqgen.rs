@@ -0,0 +1,31 @@
+/*
+ * A trivial interrupt generator, useful for tests: the guest drives /IRQ writing its single
+ * register. Bit 0 is the line itself, active low: write 0 to assert, 1 to release.
+ */
+
+use devices::Device;
+
+pub struct IrqGenerator {
+    irq: bool,
+}
+
+impl IrqGenerator {
+    pub fn new() -> IrqGenerator {
+        IrqGenerator { irq: false }
+    }
+}
+
+impl Device for IrqGenerator {
+    fn read(&mut self, _: u16) -> u8 {
+        if self.irq { 0x00 } else { 0x01 }
+    }
+    fn write(&mut self, _: u16, v: u8) {
+        self.irq = v & 0x01 == 0;
+    }
+    fn next_event(&self) -> Option<u64> {
+        None        // only the guest moves the line
+    }
+    fn irq(&self) -> bool {
+        self.irq
+    }
+}
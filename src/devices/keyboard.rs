@@ -0,0 +1,151 @@
+/*
+ * A keyboard: keys typed on the host wait in a queue, nothing is lost to fast typing or pasting.
+ *
+ *   0  data, read: the next key, 0 if none. Taken off the queue
+ *   1  status, read: bit 0 a key is waiting, bit 7 interrupt asserted
+ *      control, write: bit 0 interrupt while a key is waiting
+ *
+ * Keys can be mapped to upper or lower case, or have it swapped, on the way in.
+ */
+
+use devices::{Device, Keys};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Case {
+    Keep,
+    Upper,
+    Lower,
+    Swap,
+}
+
+pub struct Keyboard {
+    keys: Keys,
+    case: Case,
+    irq_enable: bool,
+}
+
+impl Keyboard {
+    pub fn new(keys: Keys, case: Case) -> Keyboard {
+        Keyboard { keys, case, irq_enable: false }
+    }
+
+    fn map(&self, k: u8) -> u8 {
+        match self.case {
+            Case::Keep => k,
+            Case::Upper => k.to_ascii_uppercase(),
+            Case::Lower => k.to_ascii_lowercase(),
+            Case::Swap if k.is_ascii_alphabetic() => k ^ 0x20,
+            Case::Swap => k,
+        }
+    }
+    fn waiting(&self) -> bool {
+        !self.keys.borrow().is_empty()
+    }
+}
+
+impl Device for Keyboard {
+    fn read(&mut self, reg: u16) -> u8 {
+        if reg & 1 == 0 {
+            let k = self.keys.borrow_mut().pop_front();
+            k.map_or(0, |k| self.map(k))
+        } else {
+            self.peek(reg)
+        }
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        if reg & 1 == 1 {
+            self.irq_enable = v & 0x01 != 0;
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        if reg & 1 == 0 {
+            let k = self.keys.borrow().front().cloned();
+            k.map_or(0, |k| self.map(k))
+        } else {
+            self.waiting() as u8 | (self.irq() as u8) << 7
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        None        // keys come from the host, which tells the bus
+    }
+    fn irq(&self) -> bool {
+        self.irq_enable && self.waiting()
+    }
+}
+
+// for the command line: case=keep|upper|lower|swap
+pub fn create(opts: &[&str], keys: &Keys) -> Result<Keyboard, String> {
+    let mut case = Case::Keep;
+    for o in opts {
+        case = match *o {
+            "case=keep" => Case::Keep,
+            "case=upper" => Case::Upper,
+            "case=lower" => Case::Lower,
+            "case=swap" => Case::Swap,
+            _ => return Err(format!("unknown keyboard option {}", o)),
+        };
+    }
+    Ok(Keyboard::new(keys.clone(), case))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+    use devices::{Device, Keys};
+    use devices::keyboard::*;
+
+    fn typed(s: &str) -> Keys {
+        Rc::new(RefCell::new(s.bytes().collect::<VecDeque<u8>>()))
+    }
+
+    #[test]
+    fn pasted_keys_wait_their_turn() {
+        let keys = typed("");
+        let mut kb = Keyboard::new(keys.clone(), Case::Keep);
+        assert_eq!(kb.read(1), 0x00);
+        assert_eq!(kb.read(0), 0);
+        keys.borrow_mut().extend(b"10 PRINT\r".iter());
+        let mut got = Vec::new();
+        while kb.read(1) & 0x01 != 0 {
+            assert_eq!(kb.peek(0), kb.peek(0));
+            got.push(kb.read(0));
+        }
+        assert_eq!(got, b"10 PRINT\r");
+        assert_eq!(kb.read(0), 0);
+    }
+
+    #[test]
+    fn interrupt_while_a_key_is_waiting() {
+        let keys = typed("ab");
+        let mut kb = Keyboard::new(keys.clone(), Case::Keep);
+        assert!(!kb.irq());
+        assert_eq!(kb.read(1), 0x01);
+        kb.write(1, 0x01);
+        assert!(kb.irq());
+        assert_eq!(kb.read(1), 0x81);
+        kb.read(0);
+        assert!(kb.irq());
+        kb.read(0);
+        assert!(!kb.irq());
+        assert_eq!(kb.read(1), 0x00);
+        keys.borrow_mut().push_back(b'c');
+        assert!(kb.irq());
+        kb.write(1, 0x00);
+        assert!(!kb.irq());
+        assert_eq!(kb.read(1), 0x01);
+    }
+
+    #[test]
+    fn case_on_the_way_in() {
+        let cases = [(Case::Keep, "aZ1!"), (Case::Upper, "AZ1!"), (Case::Lower, "az1!"), (Case::Swap, "Az1!")];
+        for &(case, expected) in cases.iter() {
+            let mut kb = Keyboard::new(typed("aZ1!"), case);
+            let got: Vec<u8> = (0..4).map(|_| kb.read(0)).collect();
+            assert_eq!(got, expected.as_bytes());
+        }
+        assert!(create(&["case=upper"], &typed("")).is_ok());
+        assert!(create(&["case=title"], &typed("")).is_err());
+    }
+}
@@ -0,0 +1,544 @@
+/*
+ * Hitachi HD44780 character LCD controller, as on the 16x2 and 20x4 modules.
+ *
+ * The chip sees RS, R/W, E and the data lines. Writes are taken on the falling edge of E, reads
+ * put the data on the lines while E is high. RS 0 is an instruction, or the busy flag and address
+ * counter on reads, RS 1 is data to or from DDRAM or CGRAM, wherever the last address set points.
+ * With the 4 bit interface (function set, DL 0) only D4-D7 are used, high nibble first. At power
+ * on the interface is 8 bits wide, D0-D3 read as 0 if not connected.
+ *
+ *   01  clear, 02 home
+ *   04  entry mode: bit 1 increment, bit 0 shift the display
+ *   08  display control: bit 2 display on, bit 1 cursor, bit 0 blink
+ *   10  shift: bit 3 display (else cursor), bit 2 right
+ *   20  function set: bit 4 8 bit interface, bit 3 two lines, bit 2 5x10 font (makes no difference)
+ *   40  CGRAM address, 80 DDRAM address
+ *
+ * Busy times are the datasheet ones at 270kHz, in cycles of a 1MHz cpu: 1520 for clear and home,
+ * 37 for the rest, 41 for data. Whatever comes while busy is lost, as on the real thing where it
+ * is trouble. The power on reset is immediate.
+ *
+ * Two lines of 40 bytes in DDRAM, at 00 and 40, or one of 80 with N 0. A 20x4 module shows line
+ * one on rows 0 and 2, line two on rows 1 and 3. Characters are the A00 ROM, ASCII but for 5C
+ * (yen) and 7E/7F (arrows), custom ones from CGRAM at 00-0F.
+ *
+ * Shown, the display is drawn in a box at the top right corner of the terminal, when it changes
+ * and at most every 20ms of wall clock. The cursor is underlined, blinking shows it reversed.
+ * Without a terminal to draw on, the text is printed at exit instead.
+ */
+
+use std::io::{stdout, Write};
+use std::time::{Duration, Instant};
+use devices::Device;
+
+const CLEAR: u64 = 1520;        // cycles
+const INSTRUCTION: u64 = 37;
+const DATA: u64 = 41;
+const REFRESH: u64 = 20;        // ms
+const POLL: u64 = 10_000;       // cycles between looks at the wall clock, with something to show
+
+pub struct Lcd {
+    cols: usize,
+    rows: usize,
+    ddram: [u8; 80],
+    cgram: [u8; 64],
+    ac: u8,
+    cg: bool,               // the address counter is in CGRAM
+    increment: bool,
+    shift_display: bool,    // on writes
+    shift: usize,           // DDRAM address of the first column, in the line
+    display: bool,
+    cursor: bool,
+    blink: bool,
+    wide: bool,             // 8 bit interface
+    two_lines: bool,
+    busy: u64,              // cycles
+    e: bool,
+    low: bool,              // 4 bit interface: the low half is next, reads and writes alike
+    high: u8,               // first half of a 4 bit write
+    out: u8,                // on the data lines while E is high, for reads
+    show: Option<Box<dyn Write>>,
+    dirty: bool,
+    since: Instant,         // last time shown
+    dump: bool,             // print the text at exit
+}
+
+impl Lcd {
+    pub fn new(cols: usize, rows: usize) -> Lcd {
+        Lcd {
+            cols,
+            rows,
+            ddram: [0x20; 80],
+            cgram: [0; 64],
+            ac: 0,
+            cg: false,
+            increment: true,
+            shift_display: false,
+            shift: 0,
+            display: false,
+            cursor: false,
+            blink: false,
+            wide: true,
+            two_lines: false,
+            busy: 0,
+            e: false,
+            low: false,
+            high: 0,
+            out: 0xFF,
+            show: None,
+            dirty: false,
+            since: Instant::now(),
+            dump: false,
+        }
+    }
+
+    // draw it on out
+    pub fn show(&mut self, out: Box<dyn Write>) {
+        self.show = Some(out);
+        self.dirty = true;
+    }
+
+    pub fn dump_at_exit(&mut self) {
+        self.dump = true;
+    }
+
+    // the pins as the master drives them, with the data lines D7-D0. What the LCD drives on
+    // the data lines back, FF while it doesn't
+    pub fn pins(&mut self, rs: bool, rw: bool, e: bool, data: u8) -> u8 {
+        if e && !self.e && rw {
+            self.out = self.read(rs);
+        } else if !e && self.e {
+            if rw {
+                if self.wide || self.low {
+                    self.advance(rs);
+                }
+                if !self.wide {
+                    self.low = !self.low;
+                }
+            } else {
+                self.latch(rs, data);
+            }
+        }
+        self.e = e;
+        if e && rw { self.out } else { 0xFF }
+    }
+
+    // the display as text, row by row. Blank when off
+    pub fn text(&self) -> Vec<String> {
+        (0..self.rows).map(|r| (0..self.cols).map(|c| match self.at(r, c) {
+            Some(v) if self.display => glyph(v),
+            _ => ' ',
+        }).collect()).collect()
+    }
+
+    pub fn tick(&mut self, cycles: u64) {
+        self.busy = self.busy.saturating_sub(cycles);
+        if self.dirty && self.show.is_some() && self.since.elapsed() >= Duration::from_millis(REFRESH) {
+            self.draw();
+        }
+    }
+    pub fn next_event(&self) -> Option<u64> {
+        if self.dirty && self.show.is_some() { Some(POLL) } else { None }
+    }
+
+    fn at(&self, row: usize, col: usize) -> Option<u8> {
+        self.shown_index(row, col).map(|i| self.ddram[i])
+    }
+
+    // DDRAM index shown at row, column. None if that row shows nothing
+    fn shown_index(&self, row: usize, col: usize) -> Option<usize> {
+        if self.two_lines {
+            Some((row % 2) * 40 + (self.shift + (row / 2) * self.cols + col) % 40)
+        } else if row == 0 {
+            Some((self.shift + col) % 80)
+        } else {
+            None
+        }
+    }
+
+    // DDRAM index of the address counter
+    fn index(&self) -> usize {
+        let a = self.ac as usize & 0x7F;
+        if !self.two_lines {
+            a % 80
+        } else if a >= 0x40 {
+            40 + (a - 0x40) % 40
+        } else {
+            a % 40
+        }
+    }
+
+    fn read(&self, rs: bool) -> u8 {
+        let v = if !rs {
+            ((self.busy > 0) as u8) << 7 | self.ac & 0x7F
+        } else if self.cg {
+            self.cgram[self.ac as usize & 0x3F]
+        } else {
+            self.ddram[self.index()]
+        };
+        if !self.wide && self.low { v << 4 | 0x0F } else { v }
+    }
+
+    // a read is over: data reads move the address counter
+    fn advance(&mut self, rs: bool) {
+        if rs {
+            self.step();
+        }
+    }
+
+    fn latch(&mut self, rs: bool, data: u8) {
+        let v = if self.wide {
+            data
+        } else if self.low {
+            self.low = false;
+            self.high | data >> 4
+        } else {
+            self.high = data & 0xF0;
+            self.low = true;
+            return;
+        };
+        if self.busy > 0 {
+            return;
+        }
+        if rs { self.data(v) } else { self.instruction(v) }
+        self.dirty = true;
+    }
+
+    fn instruction(&mut self, v: u8) {
+        if v == 0 {
+            return;     // no such instruction, nothing happens. E.g. the pins settling at power on
+        }
+        self.busy = INSTRUCTION;
+        match v.leading_zeros() {
+            7 => {
+                self.ddram = [0x20; 80];
+                self.ac = 0;
+                self.cg = false;
+                self.shift = 0;
+                self.increment = true;
+                self.busy = CLEAR;
+            }
+            6 => {
+                self.ac = 0;
+                self.cg = false;
+                self.shift = 0;
+                self.busy = CLEAR;
+            }
+            5 => {
+                self.increment = v & 0x02 != 0;
+                self.shift_display = v & 0x01 != 0;
+            }
+            4 => {
+                self.display = v & 0x04 != 0;
+                self.cursor = v & 0x02 != 0;
+                self.blink = v & 0x01 != 0;
+            }
+            3 => {
+                let right = v & 0x04 != 0;
+                if v & 0x08 != 0 {
+                    self.shift_by(!right);
+                } else {
+                    let inc = self.increment;
+                    self.increment = right;
+                    self.step();
+                    self.increment = inc;
+                }
+            }
+            2 => {
+                self.wide = v & 0x10 != 0;
+                self.two_lines = v & 0x08 != 0;
+                self.low = false;
+            }
+            1 => {
+                self.ac = v & 0x3F;
+                self.cg = true;
+            }
+            0 => {
+                self.ac = v & 0x7F;
+                self.cg = false;
+            }
+            _ => {}
+        }
+    }
+
+    fn data(&mut self, v: u8) {
+        self.busy = DATA;
+        if self.cg {
+            self.cgram[self.ac as usize & 0x3F] = v & 0x1F;
+        } else {
+            let i = self.index();
+            self.ddram[i] = v;
+            if self.shift_display {
+                let left = self.increment;
+                self.shift_by(left);
+            }
+        }
+        self.step();
+    }
+
+    // the display moves left (the first column shows the next address) or right
+    fn shift_by(&mut self, left: bool) {
+        let len = if self.two_lines { 40 } else { 80 };
+        self.shift = if left { (self.shift + 1) % len } else { (self.shift + len - 1) % len };
+    }
+
+    // address counter to the next or previous address
+    fn step(&mut self) {
+        if self.cg {
+            self.ac = if self.increment { self.ac + 1 } else { self.ac.wrapping_sub(1) } & 0x3F;
+            return;
+        }
+        let a = self.ac & 0x7F;
+        self.ac = match (self.two_lines, self.increment) {
+            (false, true) => if a >= 0x4F { 0 } else { a + 1 },
+            (false, false) => if a == 0 { 0x4F } else { a - 1 },
+            (true, true) if a == 0x27 => 0x40,
+            (true, true) if a >= 0x67 => 0,
+            (true, true) => a + 1,
+            (true, false) if a == 0x40 => 0x27,
+            (true, false) if a == 0 => 0x67,
+            (true, false) => a - 1,
+        };
+    }
+
+    fn draw(&mut self) {
+        let text = self.text();
+        let cursor = if self.display && !self.cg && (self.cursor || self.blink) {
+            (0..self.rows).flat_map(|r| (0..self.cols).map(move |c| (r, c)))
+                .find(|&(r, c)| self.shown_index(r, c) == Some(self.index()))
+        } else {
+            None
+        };
+        let mut s = String::from("\x1B7");
+        let border = "-".repeat(self.cols);
+        let width = self.cols + 2;
+        s.push_str(&format!("\x1B[1;999H\x1B[{}D+{}+", width - 1, border));
+        for (r, line) in text.iter().enumerate() {
+            s.push_str(&format!("\x1B[{};999H\x1B[{}D|", r + 2, width - 1));
+            for (c, ch) in line.chars().enumerate() {
+                if cursor == Some((r, c)) {
+                    s.push_str(if self.blink { "\x1B[7m" } else { "\x1B[4m" });
+                    s.push(ch);
+                    s.push_str("\x1B[0m");
+                } else {
+                    s.push(ch);
+                }
+            }
+            s.push('|');
+        }
+        s.push_str(&format!("\x1B[{};999H\x1B[{}D+{}+\x1B8", self.rows + 2, width - 1, border));
+        if let Some(ref mut out) = self.show {
+            let _ = out.write_all(s.as_bytes());
+            let _ = out.flush();
+        }
+        self.dirty = false;
+        self.since = Instant::now();
+    }
+}
+
+impl Drop for Lcd {
+    fn drop(&mut self) {
+        if self.dirty && self.show.is_some() {
+            self.draw();
+        }
+        if self.dump {
+            let mut out = stdout();
+            for line in self.text() {
+                let _ = write!(out, "{}\r\n", line.trim_end());
+            }
+            let _ = out.flush();
+        }
+    }
+}
+
+// A00 character ROM, what of it has a close enough unicode
+fn glyph(v: u8) -> char {
+    match v {
+        0x00..=0x0F => '\u{2592}',      // custom
+        0x5C => '\u{a5}',
+        0x7E => '\u{2192}',
+        0x7F => '\u{2190}',
+        0x20..=0x7D => v as char,
+        0xDF => '\u{b0}',
+        0xE0 => '\u{3b1}',
+        0xE2 => '\u{3b2}',
+        0xE4 => '\u{3bc}',
+        0xF4 => '\u{3a9}',
+        0xF7 => '\u{3c0}',
+        0xFF => '\u{2588}',
+        _ => '?',
+    }
+}
+
+// on the cpu bus: RS is A0, E pulses at every access, 8 bit interface
+pub struct Mapped(pub Lcd);
+
+impl Device for Mapped {
+    fn read(&mut self, reg: u16) -> u8 {
+        let rs = reg & 1 != 0;
+        self.0.pins(rs, true, true, 0xFF);
+        self.0.pins(rs, true, false, 0xFF);
+        self.0.out
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        let rs = reg & 1 != 0;
+        self.0.pins(rs, false, true, v);
+        self.0.pins(rs, false, false, v);
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.0.read(reg & 1 != 0)
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.0.tick(cycles);
+    }
+    fn next_event(&self) -> Option<u64> {
+        self.0.next_event()
+    }
+}
+
+// for the command line: size=colsxrows (16x2), show
+pub fn create(opts: &[&str]) -> Result<Lcd, String> {
+    let mut lcd = Lcd::new(16, 2);
+    let mut show = false;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("show"), None) => show = true,
+            (Some("size"), Some(v)) => {
+                let size: Vec<usize> = v.split('x').filter_map(|n| n.parse().ok()).collect();
+                match size[..] {
+                    [c, r] if c > 0 && c <= 40 && r > 0 && r <= 4 && c * r <= 80 => lcd = Lcd::new(c, r),
+                    _ => return Err(format!("bad lcd size {}", v)),
+                }
+            }
+            _ => return Err(format!("unknown lcd option {}", o)),
+        }
+    }
+    if show && ::termion::is_tty(&stdout()) {
+        lcd.show(Box::new(stdout()));
+    } else if show {
+        lcd.dump_at_exit();
+    }
+    Ok(lcd)
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::lcd::*;
+
+    // a 4 bit write, D4-D7 only
+    fn nibbles(lcd: &mut Lcd, rs: bool, v: u8) {
+        for &n in &[v & 0xF0, v << 4] {
+            lcd.pins(rs, false, true, n);
+            lcd.pins(rs, false, false, n);
+        }
+    }
+    fn read_nibble(lcd: &mut Lcd, rs: bool) -> u8 {
+        let v = lcd.pins(rs, true, true, 0xFF);
+        lcd.pins(rs, true, false, 0xFF);
+        v & 0xF0
+    }
+
+    #[test]
+    fn eight_bit_on_the_bus() {
+        let mut lcd = Mapped(Lcd::new(16, 2));
+        for &i in &[0x38, 0x0C, 0x06, 0x01] {
+            while lcd.read(0) & 0x80 != 0 {
+                lcd.tick(1);
+            }
+            lcd.write(0, i);
+        }
+        assert_eq!(lcd.read(0), 0x80);
+        lcd.tick(CLEAR);
+        for &c in b"Hi" {
+            lcd.write(1, c);
+            lcd.tick(DATA);
+        }
+        lcd.write(0, 0xC0);
+        lcd.tick(INSTRUCTION);
+        lcd.write(1, b'!');
+        lcd.write(1, b'?');             // busy, lost
+        lcd.tick(DATA);
+        assert_eq!(lcd.read(0), 0x41);
+        assert_eq!(lcd.0.text(), ["Hi              ", "!               "]);
+        lcd.write(0, 0x80);
+        lcd.tick(INSTRUCTION);
+        assert_eq!(lcd.read(1), b'H');  // and on to the next
+        assert_eq!(lcd.read(1), b'i');
+    }
+
+    #[test]
+    fn four_bit_init_and_busy_flag() {
+        let mut lcd = Lcd::new(16, 2);
+        for &n in &[0x30, 0x30, 0x30, 0x20] {
+            lcd.pins(false, false, true, n);
+            lcd.pins(false, false, false, n);
+            lcd.tick(INSTRUCTION);
+        }
+        nibbles(&mut lcd, false, 0x28);
+        assert_eq!(read_nibble(&mut lcd, false), 0x80);
+        assert_eq!(read_nibble(&mut lcd, false), 0x00);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, false, 0x0C);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, false, 0xC5);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, true, b'A');
+        lcd.tick(DATA);
+        assert_eq!(read_nibble(&mut lcd, false), 0x40);
+        assert_eq!(read_nibble(&mut lcd, false), 0x60);
+        assert_eq!(lcd.text()[1], "     A          ");
+    }
+
+    #[test]
+    fn reads_and_writes_share_the_nibble_phase() {
+        let mut lcd = Lcd::new(16, 2);
+        lcd.pins(false, false, true, 0x20);
+        lcd.pins(false, false, false, 0x20);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, false, 0x0C);
+        lcd.tick(INSTRUCTION);
+        assert_eq!(read_nibble(&mut lcd, false), 0x00);
+        lcd.pins(false, false, true, 0x10);   // half a read, so this is a low half: 0C then 01, clear
+        lcd.pins(false, false, false, 0x10);
+        assert_eq!(read_nibble(&mut lcd, false), 0x80);
+        // the 3 3 3 2 init gets back in step, whatever the phase
+        for &n in &[0x30, 0x30, 0x30, 0x20] {
+            lcd.tick(CLEAR);
+            lcd.pins(false, false, true, n);
+            lcd.pins(false, false, false, n);
+        }
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, false, 0x08);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, false, 0x0C);
+        lcd.tick(INSTRUCTION);
+        nibbles(&mut lcd, true, b'x');
+        assert_eq!(lcd.text()[0], "x               ");
+    }
+
+    #[test]
+    fn four_rows_show_two_lines() {
+        let mut lcd = Mapped(Lcd::new(20, 4));
+        for &i in &[0x38, 0x0C] {
+            lcd.write(0, i);
+            lcd.tick(INSTRUCTION);
+        }
+        for i in 0..80u8 {
+            if i == 40 {
+                lcd.write(0, 0xC0);
+                lcd.tick(INSTRUCTION);
+            }
+            lcd.write(1, b'0' + i % 40);
+            lcd.tick(DATA);
+        }
+        let text = lcd.0.text();
+        assert_eq!(text[0], "0123456789:;<=>?@ABC");
+        assert_eq!(text[2], "DEFGHIJKLMNOPQRSTUVW");
+        assert_eq!(text[1], text[0]);
+        assert_eq!(text[3], text[2]);
+        lcd.write(0, 0x08);             // off
+        assert_eq!(lcd.0.text()[0], " ".repeat(20));
+    }
+}
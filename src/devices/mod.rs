@@ -0,0 +1,123 @@
+/*
+ * Devices hanging on the bus: timers, UARTs, video chips and the like.
+ *
+ * A device sees its registers numbered from 0, wherever it is attached. The bus wires its
+ * interrupt outputs to the cpu /IRQ and /NMI lines (wired-OR, like open collector outputs).
+ * Write a device once and plug it into any machine configuration.
+ *
+ * Time is kept by the bus, in cpu cycles wait states included, and handed out lazily: a device
+ * is caught up with tick() right before any register access, and when the event it asked for
+ * with next_event() is due. tick(n) must do what n tick(1) would. Outputs (interrupts, mostly)
+ * can change only there, so a device whose outputs move by themselves must schedule an event.
+ */
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use cpu::BusFault;
+
+pub mod irqgen;
+pub mod via;
+pub mod acia;
+pub mod cia;
+pub mod riot;
+pub mod pia;
+pub mod keyboard;
+pub mod console;
+pub mod timer;
+pub mod debug;
+pub mod disk;
+pub mod spi;
+pub mod sdcard;
+pub mod i2c;
+pub mod eeprom;
+pub mod rtc;
+pub mod lcd;
+pub mod screen;
+pub mod image;
+pub mod bitmap;
+pub mod tms9918;
+
+// keys typed on the host, for whatever device reads them. More devices reading the console share them
+pub type Keys = Rc<RefCell<VecDeque<u8>>>;
+
+pub trait Device {
+    fn read(&mut self, reg: u16) -> u8;
+    fn write(&mut self, reg: u16, v: u8);
+    // debugger access: no side effects. Devices clearing flags on read must override it
+    fn peek(&mut self, reg: u16) -> u8 { self.read(reg) }
+    fn poke(&mut self, reg: u16, v: u8) { self.write(reg, v) }
+    // time goes on, cycles at a time
+    fn tick(&mut self, _cycles: u64) {}
+    // cycles from now to the next time the device changes its outputs by itself. None if it won't.
+    // Devices that don't know better are clocked every cycle
+    fn next_event(&self) -> Option<u64> { Some(1) }
+    // state of the interrupt outputs, true if asserted
+    fn irq(&self) -> bool { false }
+    fn nmi(&self) -> bool { false }
+    // a fault raised since the last call, if any, reported as a bus error
+    fn fault(&mut self) -> Option<BusFault> { None }
+    // a transfer the device wants done by the bus, taken once. Looked at with the outputs
+    fn dma(&mut self) -> Option<Dma> { None }
+    // what a Dma::Read brought from memory
+    fn dma_read(&mut self, _data: Vec<u8>) {}
+}
+
+// bus mastering, the device moves memory by itself. A byte a cycle, the cpu waits
+pub enum Dma {
+    Read(u16, usize),       // memory to the device: address, length
+    Write(u16, Vec<u8>),    // the device to memory: address, bytes
+}
+
+// a shared device: the bus owns one handle, the host side keeps another to drive the pins
+impl<T: Device> Device for Rc<RefCell<T>> {
+    fn read(&mut self, reg: u16) -> u8 { self.borrow_mut().read(reg) }
+    fn write(&mut self, reg: u16, v: u8) { self.borrow_mut().write(reg, v) }
+    fn peek(&mut self, reg: u16) -> u8 { self.borrow_mut().peek(reg) }
+    fn poke(&mut self, reg: u16, v: u8) { self.borrow_mut().poke(reg, v) }
+    fn tick(&mut self, cycles: u64) { self.borrow_mut().tick(cycles) }
+    fn next_event(&self) -> Option<u64> { self.borrow().next_event() }
+    fn irq(&self) -> bool { self.borrow().irq() }
+    fn nmi(&self) -> bool { self.borrow().nmi() }
+    fn fault(&mut self) -> Option<BusFault> { self.borrow_mut().fault() }
+    fn dma(&mut self) -> Option<Dma> { self.borrow_mut().dma() }
+    fn dma_read(&mut self, data: Vec<u8>) { self.borrow_mut().dma_read(data) }
+}
+
+// a device by name, for the command line, and the size of its register window
+pub fn create(name: &str, opts: &[&str], keys: &Keys) -> Result<(Box<dyn Device>, usize), String> {
+    match name {
+        "via" | "6522" => match opts {
+            [] => Ok((Box::new(via::Via::new()), 16)),
+            _ => via::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 16)),
+        },
+        "acia" | "6551" => acia::create(opts, keys).map(|d| (Box::new(d) as Box<dyn Device>, 4)),
+        "cia" | "6526" => cia::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 16)),
+        "riot" | "6532" => Ok((Box::new(riot::Riot::new()), 256)),
+        "keyboard" => keyboard::create(opts, keys).map(|d| (Box::new(d) as Box<dyn Device>, 2)),
+        "console" => console::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 2)),
+        "timer" => timer::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 6)),
+        "spi" | "65spi" => spi::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 4)),
+        "lcd" | "hd44780" => lcd::create(opts).map(|d| (Box::new(lcd::Mapped(d)) as Box<dyn Device>, 2)),
+        "screen" => screen::create(opts).map(|d| {
+            let len = d.len();
+            (Box::new(d) as Box<dyn Device>, len)
+        }),
+        "bitmap" => bitmap::create(opts).map(|d| {
+            let len = d.len();
+            (Box::new(d) as Box<dyn Device>, len)
+        }),
+        "tms9918" | "vdp" => tms9918::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 2)),
+        "disk" => disk::create(opts).map(|d| (Box::new(d) as Box<dyn Device>, 8)),
+        "debug" => match opts {
+            [] => Ok((Box::new(debug::Debug::new(Box::new(::std::io::stdout()))), 7)),
+            _ => Err(format!("unknown debug options {}", opts.join(":"))),
+        },
+        "pia" | "6821" => match opts {
+            [] => Ok((Box::new(pia::Pia::new()) as Box<dyn Device>, 4)),
+            ["apple1"] => Ok((Box::new(pia::Apple1::new(keys.clone(), Box::new(::std::io::stdout()))) as Box<dyn Device>, 4)),
+            _ => Err(format!("unknown pia options {}", opts.join(":"))),
+        },
+        _ => Err(format!("no device called {}", name)),
+    }
+}
@@ -0,0 +1,354 @@
+/*
+ * Motorola 6821 PIA, Peripheral Interface Adapter: two 8 bit ports, each with a control
+ * register, two control lines and an interrupt output. Four registers, mirrored over the window:
+ * PRA/DDRA, CRA, PRB/DDRB, CRB. Bit 2 of a control register says whether its port register
+ * slot shows the data or the direction register.
+ *
+ * Control register bits: 0 C1 interrupt enable, 1 C1 active edge (1 rising), 2 data/DDR, 3-5 C2
+ * control, 6 C2 flag, 7 C1 flag. Flags are cleared reading the data register. C2 as an output
+ * does handshake (low on a port A read or port B write, high on the C1 active edge), pulse
+ * (low for one cycle) or just follows bit 3.
+ *
+ * IRQA and IRQB are both wired to the cpu /IRQ. Pins float high, as on the VIA.
+ *
+ * Apple1 is the Apple-1 keyboard and display on a PIA: keys from the host on port A with bit 7
+ * set and a CA1 strobe, one at a time as the program reads them, and port B writes go to the
+ * display. Enough for Woz Monitor.
+ */
+
+use std::io::Write;
+use devices::{Device, Keys};
+
+struct Side {
+    or: u8,
+    ddr: u8,
+    cr: u8,             // bits 0-5, flags are apart
+    pins: u8,           // driven from outside
+    c1: bool,
+    c2_in: bool,
+    c2_out: bool,
+    pulse: bool,        // c2_out goes back high next cycle
+    irq1: bool,
+    irq2: bool,
+}
+
+impl Side {
+    fn new() -> Side {
+        Side { or: 0, ddr: 0, cr: 0, pins: 0xFF, c1: true, c2_in: true, c2_out: true, pulse: false, irq1: false, irq2: false }
+    }
+    fn port(&self) -> u8 {
+        self.or & self.ddr | self.pins & !self.ddr
+    }
+    fn c2_output(&self) -> bool {
+        self.cr & 0x20 != 0
+    }
+    fn c2(&self) -> bool {
+        if self.c2_output() { self.c2_out } else { self.c2_in }
+    }
+    fn control(&self) -> u8 {
+        self.cr | (self.irq2 as u8) << 6 | (self.irq1 as u8) << 7
+    }
+    fn write_control(&mut self, v: u8) {
+        self.cr = v & 0x3F;
+        if self.c2_output() {
+            self.irq2 = false;
+            if self.cr & 0x10 != 0 {
+                self.c2_out = self.cr & 0x08 != 0;  // manual
+            }
+        }
+    }
+    // data register accessed, by the side of the handshake
+    fn handshake(&mut self) {
+        self.irq1 = false;
+        self.irq2 = false;
+        if self.cr & 0x30 == 0x20 {
+            self.c2_out = false;
+            self.pulse = self.cr & 0x08 != 0;
+        }
+    }
+    fn set_c1(&mut self, level: bool) {
+        if level != self.c1 && level == (self.cr & 0x02 != 0) {
+            self.irq1 = true;
+            if self.cr & 0x38 == 0x20 {
+                self.c2_out = true;     // handshake done
+            }
+        }
+        self.c1 = level;
+    }
+    fn set_c2(&mut self, level: bool) {
+        if !self.c2_output() && level != self.c2_in && level == (self.cr & 0x10 != 0) {
+            self.irq2 = true;
+        }
+        self.c2_in = level;
+    }
+    fn irq(&self) -> bool {
+        self.irq1 && self.cr & 0x01 != 0 || self.irq2 && self.cr & 0x08 != 0 && !self.c2_output()
+    }
+}
+
+pub struct Pia {
+    a: Side,
+    b: Side,
+}
+
+impl Pia {
+    pub fn new() -> Pia {
+        Pia { a: Side::new(), b: Side::new() }
+    }
+
+    fn register(&self, reg: u16) -> u8 {
+        match reg & 3 {
+            0 => if self.a.cr & 0x04 != 0 { self.a.port() } else { self.a.ddr },
+            1 => self.a.control(),
+            2 => if self.b.cr & 0x04 != 0 { self.b.port() } else { self.b.ddr },
+            _ => self.b.control(),
+        }
+    }
+}
+
+// the pins, for whoever is on the other side: the tests, or a machine wiring them up
+#[cfg_attr(not(test), allow(dead_code))]
+impl Pia {
+    pub fn port_a(&self) -> u8 { self.a.port() }
+    pub fn port_b(&self) -> u8 { self.b.port() }
+    pub fn ca2(&self) -> bool { self.a.c2() }
+    pub fn cb2(&self) -> bool { self.b.c2() }
+    pub fn irqa(&self) -> bool { self.a.irq() }
+    pub fn irqb(&self) -> bool { self.b.irq() }
+    pub fn set_port_a(&mut self, v: u8) { self.a.pins = v; }
+    pub fn set_port_b(&mut self, v: u8) { self.b.pins = v; }
+    pub fn set_ca1(&mut self, level: bool) { self.a.set_c1(level); }
+    pub fn set_ca2(&mut self, level: bool) { self.a.set_c2(level); }
+    pub fn set_cb1(&mut self, level: bool) { self.b.set_c1(level); }
+    pub fn set_cb2(&mut self, level: bool) { self.b.set_c2(level); }
+}
+
+impl Device for Pia {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        match reg & 3 {
+            0 if self.a.cr & 0x04 != 0 => self.a.handshake(),
+            2 if self.b.cr & 0x04 != 0 => {
+                // port B handshakes on writes, a read just clears the flags
+                self.b.irq1 = false;
+                self.b.irq2 = false;
+            }
+            _ => {}
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 3 {
+            0 => if self.a.cr & 0x04 != 0 { self.a.or = v } else { self.a.ddr = v },
+            1 => self.a.write_control(v),
+            2 => {
+                if self.b.cr & 0x04 != 0 {
+                    self.b.or = v;
+                    self.b.handshake();
+                } else {
+                    self.b.ddr = v;
+                }
+            }
+            _ => self.b.write_control(v),
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, _cycles: u64) {
+        for s in [&mut self.a, &mut self.b].iter_mut() {
+            if s.pulse {
+                s.pulse = false;
+                s.c2_out = true;
+            }
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.a.pulse || self.b.pulse { Some(1) } else { None }
+    }
+    fn irq(&self) -> bool {
+        self.a.irq() || self.b.irq()
+    }
+}
+
+pub struct Apple1 {
+    pia: Pia,
+    keys: Keys,
+    display: Box<dyn Write>,
+}
+
+impl Apple1 {
+    pub fn new(keys: Keys, display: Box<dyn Write>) -> Apple1 {
+        let mut pia = Pia::new();
+        pia.set_port_b(0x7F);       // PB7 is display busy, and we never are
+        Apple1 { pia, keys, display }
+    }
+
+    // next key on port A, when the last one has been taken
+    fn keyboard(&mut self) {
+        if self.pia.a.irq1 {
+            return;
+        }
+        if let Some(k) = self.keys.borrow_mut().pop_front() {
+            let k = match k {
+                0x08 | 0x7F => b'_',    // rubout
+                0x0A => 0x0D,
+                k => k.to_ascii_uppercase(),
+            };
+            self.pia.set_port_a(k | 0x80);
+            self.pia.set_ca1(!self.pia.a.c1);
+            self.pia.set_ca1(!self.pia.a.c1);    // a strobe, whatever the active edge
+        }
+    }
+}
+
+impl Device for Apple1 {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.pia.read(reg)
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        self.pia.write(reg, v);
+        if reg & 3 == 2 && self.pia.b.cr & 0x04 != 0 {
+            let _ = match v & 0x7F {
+                0x0D => self.display.write(b"\r\n"),
+                c if c >= 0x20 => self.display.write(&[c]),
+                _ => Ok(0),
+            };
+            let _ = self.display.flush();
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.pia.peek(reg)
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.pia.tick(cycles);
+        self.keyboard();
+    }
+    fn next_event(&self) -> Option<u64> {
+        if !self.pia.a.irq1 && !self.keys.borrow().is_empty() {
+            Some(1)
+        } else {
+            self.pia.next_event()
+        }
+    }
+    fn irq(&self) -> bool {
+        self.pia.irq()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use devices::{Device, Keys};
+    use devices::pia::*;
+
+    #[derive(Clone, Default)]
+    struct Tape(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Tape {
+        fn write(&mut self, b: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(b);
+            Ok(b.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn ports_behind_the_ddr_bit() {
+        let mut pia = Pia::new();
+        pia.write(0, 0x0F);             // CRA bit 2 clear: DDRA
+        pia.write(1, 0x04);
+        pia.write(0, 0x05);
+        pia.set_port_a(0xA0);
+        assert_eq!(pia.read(0), 0xA5);
+        assert_eq!(pia.port_a(), 0xA5);
+        pia.write(2, 0xFF);
+        pia.write(3, 0x04);
+        pia.write(2, 0x3C);
+        pia.set_port_b(0x00);
+        assert_eq!(pia.port_b(), 0x3C);
+    }
+
+    #[test]
+    fn ca2_handshakes_port_a_reads() {
+        let mut pia = Pia::new();
+        pia.write(1, 0x25);             // CA1 falling, enabled, CA2 handshake
+        assert!(pia.ca2());
+        pia.read(0);
+        assert!(!pia.ca2());
+        pia.set_ca1(false);
+        assert!(pia.ca2());
+        assert!(pia.irqa() && pia.irq());
+        assert_eq!(pia.read(1), 0xA5);
+        pia.read(0);
+        assert!(!pia.irqa());
+        pia.set_ca2(false);             // an output, the pin changes nothing
+        assert_eq!(pia.read(1) & 0x40, 0);
+    }
+
+    #[test]
+    fn cb2_pulses_on_port_b_writes() {
+        let mut pia = Pia::new();
+        pia.write(3, 0x2C);             // CB2 pulse output
+        pia.write(2, 0x00);
+        assert!(!pia.cb2());
+        pia.tick(1);
+        assert!(pia.cb2());
+    }
+
+    #[test]
+    fn cb1_and_cb2_inputs() {
+        let mut pia = Pia::new();
+        pia.write(3, 0x1F);             // CB1 rising, CB2 rising, both enabled
+        pia.set_cb1(false);
+        pia.set_cb2(false);
+        assert!(!pia.irqb());
+        pia.set_cb1(true);
+        assert_eq!(pia.read(3), 0x9F);
+        pia.set_cb2(true);
+        assert_eq!(pia.read(3), 0xDF);
+        assert!(pia.irqb());
+    }
+
+    #[test]
+    fn apple1_keys_and_display() {
+        let keys = Keys::new(RefCell::new(b"a\n".iter().cloned().collect()));
+        let tape = Tape::default();
+        let mut a1 = Apple1::new(keys.clone(), Box::new(tape.clone()));
+        // Woz Monitor: DDRB out but PB7, both sides on their port, CA1 rising
+        a1.write(2, 0x7F);
+        a1.write(1, 0xA7);
+        a1.write(3, 0xA7);
+        assert_eq!(a1.next_event(), Some(1));
+        a1.tick(1);
+        assert_eq!(a1.read(1) & 0x80, 0x80);
+        assert_eq!(a1.read(0), 0xC1);
+        assert_eq!(a1.read(1) & 0x80, 0);
+        a1.tick(1);
+        assert_eq!(a1.read(0), 0x8D);   // newline is return
+        assert!(keys.borrow().is_empty());
+        assert_eq!(a1.read(2) & 0x80, 0);
+        for &c in b"HI\r" {
+            a1.write(2, c | 0x80);
+        }
+        assert_eq!(*tape.0.borrow(), b"HI\r\n");
+    }
+
+    #[test]
+    fn apple1_waits_for_the_key_to_be_taken() {
+        let keys = Keys::new(RefCell::new(b"\x08x".iter().cloned().collect()));
+        let mut a1 = Apple1::new(keys, Box::new(io::sink()));
+        a1.write(1, 0x04);
+        a1.tick(10);
+        assert_eq!(a1.peek(0), b'_' | 0x80);
+        assert_eq!(a1.next_event(), None);
+        a1.tick(10);
+        assert_eq!(a1.read(0), b'_' | 0x80);
+        a1.tick(1);
+        assert_eq!(a1.read(0), b'X' | 0x80);
+    }
+}
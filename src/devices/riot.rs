@@ -0,0 +1,254 @@
+/*
+ * MOS 6532 RIOT, RAM-I/O-Timer: 128 bytes of RAM, two 8 bit ports, an interval timer and an
+ * edge detector on PA7.
+ *
+ * The chip selects RAM or I/O with its RS pin, here address bit 7: the window is 256 bytes, RAM in
+ * the lower half, I/O in the upper one decoded from A0-A4 like the chip does.
+ *
+ *   A2 = 0:        A1 A0 select PA, DDRA, PB, DDRB
+ *   A2 = 1, read:  A0 = 0 timer, A0 = 1 interrupt flags (timer bit 7, PA7 bit 6)
+ *   A2 = 1, write: A4 = 1 timer, A1 A0 the prescaler 1/8/64/1024
+ *                  A4 = 0 edge detect, A0 positive edge, A1 PA7 interrupt enable
+ *   A3 enables the timer interrupt on timer reads and writes.
+ *
+ * The timer counts down at the prescaler rate, first decrement the cycle after the write. Past
+ * zero it sets the flag and goes on at one count per cycle from FF, until the timer is read:
+ * that clears the flag and gets the prescaler back. Pins float high, as on the VIA.
+ */
+
+use devices::Device;
+
+const TIMER: u8 = 0x80;
+const PA7: u8 = 0x40;
+
+pub struct Riot {
+    ram: [u8; 128],
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,
+    pb_in: u8,
+    timer: u8,
+    prescaler: u64,     // programmed
+    interval: u64,      // in use, 1 once past zero
+    sub: u64,           // cycles to the next decrement
+    flags: u8,
+    timer_irq: bool,
+    pa7_irq: bool,
+    pa7_rising: bool,   // edge the detector looks for
+}
+
+impl Riot {
+    pub fn new() -> Riot {
+        Riot {
+            ram: [0; 128],
+            ora: 0, orb: 0, ddra: 0, ddrb: 0, pa_in: 0xFF, pb_in: 0xFF,
+            timer: 0xFF, prescaler: 1024, interval: 1024, sub: 1024,
+            flags: 0, timer_irq: false, pa7_irq: false, pa7_rising: false,
+        }
+    }
+
+    pub fn port_a(&self) -> u8 {
+        self.ora & self.ddra | self.pa_in & !self.ddra
+    }
+    pub fn port_b(&self) -> u8 {
+        self.orb & self.ddrb | self.pb_in & !self.ddrb
+    }
+
+    // PA7 edge detector, from the pins as they were
+    fn edge(&mut self, before: u8) {
+        let after = self.port_a();
+        if (before ^ after) & 0x80 != 0 && (after & 0x80 != 0) == self.pa7_rising {
+            self.flags |= PA7;
+        }
+    }
+
+    // cycles to the timer going past zero
+    fn to_underflow(&self) -> u64 {
+        self.sub + self.timer as u64 * self.interval
+    }
+
+    fn register(&self, reg: u16) -> u8 {
+        if reg & 0x80 == 0 {
+            return self.ram[(reg & 0x7F) as usize];
+        }
+        if reg & 0x04 == 0 {
+            return match reg & 0x03 {
+                0 => self.port_a(),
+                1 => self.ddra,
+                2 => self.port_b(),
+                _ => self.ddrb,
+            };
+        }
+        if reg & 0x01 == 0 { self.timer } else { self.flags }
+    }
+}
+
+// the pins, for whoever is on the other side: the tests, or a machine wiring them up
+#[cfg_attr(not(test), allow(dead_code))]
+impl Riot {
+    pub fn set_port_a(&mut self, v: u8) {
+        let before = self.port_a();
+        self.pa_in = v;
+        self.edge(before);
+    }
+    pub fn set_port_b(&mut self, v: u8) {
+        self.pb_in = v;
+    }
+}
+
+impl Device for Riot {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        if reg & 0x84 == 0x84 {
+            if reg & 0x01 == 0 {
+                self.timer_irq = reg & 0x08 != 0;
+                if self.flags & TIMER != 0 {
+                    self.flags &= !TIMER;
+                    self.interval = self.prescaler;
+                }
+            } else {
+                self.flags &= !PA7;
+            }
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        if reg & 0x80 == 0 {
+            self.ram[(reg & 0x7F) as usize] = v;
+            return;
+        }
+        if reg & 0x04 == 0 {
+            let before = self.port_a();
+            match reg & 0x03 {
+                0 => self.ora = v,
+                1 => self.ddra = v,
+                2 => self.orb = v,
+                _ => self.ddrb = v,
+            }
+            self.edge(before);
+            return;
+        }
+        if reg & 0x10 != 0 {
+            self.prescaler = [1, 8, 64, 1024][(reg & 0x03) as usize];
+            self.interval = self.prescaler;
+            self.sub = 1;
+            self.timer = v;
+            self.timer_irq = reg & 0x08 != 0;
+            self.flags &= !TIMER;
+        } else {
+            self.pa7_rising = reg & 0x01 != 0;
+            self.pa7_irq = reg & 0x02 != 0;
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while cycles > 0 {
+            if self.flags & TIMER != 0 {
+                // past zero, one a cycle, nothing else can happen
+                self.timer = self.timer.wrapping_sub(cycles as u8);
+                return;
+            }
+            let left = self.to_underflow();
+            if cycles < left {
+                // just counting
+                if cycles >= self.sub {
+                    let n = cycles - self.sub;
+                    self.timer -= (1 + n / self.interval) as u8;
+                    self.sub = self.interval - n % self.interval;
+                } else {
+                    self.sub -= cycles;
+                }
+                return;
+            }
+            cycles -= left;
+            self.flags |= TIMER;
+            self.interval = 1;
+            self.timer = 0xFF;
+            self.sub = 1;
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.flags & TIMER == 0 { Some(self.to_underflow()) } else { None }
+    }
+    fn irq(&self) -> bool {
+        self.timer_irq && self.flags & TIMER != 0 || self.pa7_irq && self.flags & PA7 != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::riot::*;
+
+    #[test]
+    fn pa7_rising_edge_interrupts() {
+        let mut riot = Riot::new();
+        riot.write(0x87, 0);            // positive edge, interrupt enabled
+        riot.set_port_a(0x00);
+        assert!(!riot.irq());
+        riot.set_port_a(0x80);
+        assert!(riot.irq());
+        assert_eq!(riot.read(0x85), PA7);
+        assert!(!riot.irq());
+    }
+
+    #[test]
+    fn port_b_mixes_outputs_and_pins() {
+        let mut riot = Riot::new();
+        riot.write(0x83, 0xF0);
+        riot.write(0x82, 0x50);
+        riot.set_port_b(0x0A);
+        assert_eq!(riot.read(0x82), 0x5A);
+    }
+
+    #[test]
+    fn ram_in_the_lower_half() {
+        let mut riot = Riot::new();
+        riot.write(0x05, 0x42);
+        riot.write(0x7F, 0x24);
+        riot.write(0x81, 0xFF);
+        assert_eq!(riot.read(0x05), 0x42);
+        assert_eq!(riot.read(0x7F), 0x24);
+        assert_eq!(riot.read(0x01), 0);
+        assert_eq!(riot.read(0x81), 0xFF);
+    }
+
+    #[test]
+    fn timer_counts_at_the_prescaler_then_every_cycle() {
+        let mut riot = Riot::new();
+        riot.write(0x9D, 3);            // 8 cycles a count, interrupt enabled
+        assert_eq!(riot.next_event(), Some(25));
+        riot.tick(1);
+        assert_eq!(riot.peek(0x84), 2);
+        riot.tick(23);
+        assert_eq!(riot.peek(0x84), 0);
+        assert!(!riot.irq());
+        riot.tick(1);
+        assert!(riot.irq());
+        assert_eq!(riot.peek(0x85), TIMER);
+        riot.tick(5);
+        assert_eq!(riot.read(0x8C), 0xFA);
+        assert!(!riot.irq());           // the read clears the flag
+        assert_eq!(riot.peek(0x85), 0);
+        riot.tick(8);                   // back to 8 cycles a count
+        assert_eq!(riot.peek(0x84), 0xF9);
+        riot.tick(1);
+        assert_eq!(riot.peek(0x84), 0xF8);
+    }
+
+    #[test]
+    fn timer_interrupt_enable_follows_a3() {
+        let mut riot = Riot::new();
+        riot.write(0x94, 1);            // 1 a count, no interrupt
+        riot.tick(3);
+        assert_eq!(riot.peek(0x85), TIMER);
+        assert!(!riot.irq());
+        riot.read(0x8C);
+        riot.tick(0x101);
+        assert!(riot.irq());
+    }
+}
@@ -0,0 +1,237 @@
+/*
+ * Maxim DS1307, I2C real time clock: BCD time and date, and 56 bytes of RAM.
+ *
+ * Address 68. A write sends the register pointer, then data from there. Reads go on from the
+ * pointer, which wraps from 3F to 0. The time is latched at START, so a read never sees it move.
+ *
+ *   0  seconds, bit 7 CH halts the clock
+ *   1  minutes
+ *   2  hours, bit 6 12 hour mode: then bit 5 PM, 1-12
+ *   3  day of the week 1-7, as the program likes it (starts with 1 for Sunday)
+ *   4  date, 5 month, 6 year 00-99 (2000-2099, every 4th a leap year)
+ *   7  control, kept but the SQW/OUT pin goes nowhere
+ *   8-3F  RAM
+ *
+ * The counters are the chip's, not a date from the host: they go up a second at a time and carry
+ * like the chip does, whatever was written in them. Starting time from the host clock, or fixed
+ * for runs to be reproducible. A second is a million cycles, writing the seconds restarts it.
+ */
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use devices::i2c::I2cDevice;
+
+const SECOND: u64 = 1_000_000;
+
+pub struct Rtc {
+    regs: [u8; 64],
+    latch: [u8; 8],
+    pointer: usize,
+    received: usize,
+    sub: u64,           // cycles into the second
+}
+
+fn bcd(v: u8) -> u8 {
+    ((v / 10) << 4) | (v % 10)
+}
+fn bin(v: u8) -> u8 {
+    (v >> 4) * 10 + (v & 0x0F)
+}
+
+fn days_in_month(month: u8, year: u8) -> u8 {
+    match month {
+        2 if year.is_multiple_of(4) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Rtc {
+    // running, from seconds since 1970
+    pub fn new(seconds: u64) -> Rtc {
+        let mut days = seconds / 86400;
+        let t = seconds % 86400;
+        let weekday = ((days + 4) % 7) as u8 + 1;       // 1970-01-01 was a Thursday
+        let leap = |y: u64| y.is_multiple_of(4) && (!y.is_multiple_of(100) || y.is_multiple_of(400));
+        let mut year = 1970;
+        while days >= if leap(year) { 366 } else { 365 } {
+            days -= if leap(year) { 366 } else { 365 };
+            year += 1;
+        }
+        let mut month = 1;
+        loop {
+            let len = if month == 2 && leap(year) { 29 } else { days_in_month(month, 1) as u64 };
+            if days < len {
+                break;
+            }
+            days -= len;
+            month += 1;
+        }
+        let mut regs = [0u8; 64];
+        regs[0] = bcd((t % 60) as u8);
+        regs[1] = bcd((t / 60 % 60) as u8);
+        regs[2] = bcd((t / 3600) as u8);
+        regs[3] = weekday;
+        regs[4] = bcd(days as u8 + 1);
+        regs[5] = bcd(month);
+        regs[6] = bcd((year % 100) as u8);
+        Rtc { regs, latch: [0; 8], pointer: 0, received: 0, sub: 0 }
+    }
+
+    pub fn host() -> Rtc {
+        Rtc::new(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+    }
+
+    fn second(&mut self) {
+        let r = &mut self.regs;
+        if r[0] & 0x80 != 0 {
+            return;
+        }
+        let s = bin(r[0]) + 1;
+        r[0] = bcd(s % 60);
+        if s < 60 {
+            return;
+        }
+        let m = bin(r[1] & 0x7F) + 1;
+        r[1] = bcd(m % 60);
+        if m < 60 {
+            return;
+        }
+        let new_day = if r[2] & 0x40 != 0 {
+            let (h, pm) = (bin(r[2] & 0x1F), r[2] & 0x20 != 0);
+            let (h, pm) = match h {
+                11 => (12, !pm),
+                12 => (1, pm),
+                h => (h % 12 + 1, pm),     // a bad hour stays in 1-12, and out of the PM bit
+            };
+            r[2] = 0x40 | (pm as u8) << 5 | bcd(h);
+            h == 12 && !pm
+        } else {
+            let h = bin(r[2] & 0x3F) + 1;
+            r[2] = bcd(h % 24);
+            h == 24
+        };
+        if !new_day {
+            return;
+        }
+        r[3] = r[3] % 7 + 1;
+        let (month, year) = (bin(r[5] & 0x1F), bin(r[6]));
+        let d = bin(r[4] & 0x3F) + 1;
+        if d <= days_in_month(month, year) {
+            r[4] = bcd(d);
+            return;
+        }
+        r[4] = 0x01;
+        if month < 12 {
+            r[5] = bcd(month + 1);
+            return;
+        }
+        r[5] = 0x01;
+        r[6] = bcd((year + 1) % 100);
+    }
+}
+
+impl I2cDevice for Rtc {
+    fn address(&self) -> u8 {
+        0x68
+    }
+    fn start(&mut self, _read: bool) -> bool {
+        self.received = 0;
+        self.latch.copy_from_slice(&self.regs[..8]);
+        true
+    }
+    fn write(&mut self, v: u8) -> bool {
+        if self.received == 0 {
+            self.pointer = v as usize & 0x3F;
+        } else {
+            self.regs[self.pointer] = v;
+            if self.pointer == 0 {
+                self.sub = 0;
+            }
+            self.pointer = (self.pointer + 1) & 0x3F;
+        }
+        self.received += 1;
+        true
+    }
+    fn read(&mut self) -> u8 {
+        let v = if self.pointer < 8 { self.latch[self.pointer] } else { self.regs[self.pointer] };
+        self.pointer = (self.pointer + 1) & 0x3F;
+        v
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.sub += cycles;
+        while self.sub >= SECOND {
+            self.sub -= SECOND;
+            self.second();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::i2c::I2cDevice;
+    use devices::rtc::*;
+
+    fn set(rtc: &mut Rtc, regs: &[u8]) {
+        rtc.start(false);
+        rtc.write(0);
+        for &v in regs {
+            rtc.write(v);
+        }
+    }
+    fn get(rtc: &mut Rtc) -> Vec<u8> {
+        rtc.start(false);
+        rtc.write(0);
+        rtc.start(true);
+        (0..7).map(|_| rtc.read()).collect()
+    }
+
+    #[test]
+    fn from_the_host_clock() {
+        // 2024-02-29 13:45:30, a Thursday
+        let mut rtc = Rtc::new(1_709_214_330);
+        assert_eq!(get(&mut rtc), [0x30, 0x45, 0x13, 0x05, 0x29, 0x02, 0x24]);
+    }
+
+    #[test]
+    fn carries_in_12_hour_mode() {
+        let mut rtc = Rtc::new(0);
+        set(&mut rtc, &[0x59, 0x59, 0x71, 0x07, 0x31, 0x12, 0x99]);    // 11:59:59 PM
+        rtc.tick(SECOND);
+        assert_eq!(get(&mut rtc), [0x00, 0x00, 0x52, 0x01, 0x01, 0x01, 0x00]);
+        set(&mut rtc, &[0x59, 0x59, 0x51]);                                  // 11:59:59 AM
+        rtc.tick(SECOND);
+        assert_eq!(get(&mut rtc)[..4], [0x00, 0x00, 0x72, 0x01]);            // noon, same day
+        set(&mut rtc, &[0x59, 0x59, 0x59]);                                  // 19 AM, nonsense
+        rtc.tick(SECOND);
+        assert_eq!(get(&mut rtc)[2], 0x48);                                  // still AM
+    }
+
+    #[test]
+    fn leap_years_and_halt() {
+        let mut rtc = Rtc::new(0);
+        set(&mut rtc, &[0x59, 0x59, 0x23, 0x01, 0x28, 0x02, 0x24]);
+        rtc.tick(SECOND);
+        assert_eq!(get(&mut rtc)[4..], [0x29, 0x02, 0x24]);
+        set(&mut rtc, &[0xD9, 0x59, 0x23, 0x01, 0x28, 0x02, 0x23]);    // CH
+        rtc.tick(10 * SECOND);
+        assert_eq!(get(&mut rtc)[0], 0xD9);
+        set(&mut rtc, &[0x59]);
+        rtc.tick(SECOND);
+        assert_eq!(get(&mut rtc)[4..], [0x01, 0x03, 0x23]);
+    }
+
+    #[test]
+    fn the_time_is_latched_at_start() {
+        let mut rtc = Rtc::new(59);
+        rtc.start(false);
+        rtc.write(0);
+        rtc.start(true);
+        assert_eq!(rtc.read(), 0x59);
+        rtc.tick(SECOND);
+        assert_eq!(rtc.read(), 0x00);       // still 00:00:59
+        rtc.start(true);
+        rtc.pointer = 1;
+        assert_eq!(rtc.read(), 0x01);
+    }
+}
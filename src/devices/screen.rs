@@ -0,0 +1,320 @@
+/*
+ * A text screen in memory: each byte of the window is a character cell, row after row, like the
+ * screen memory of the C64 (40x25 at 0400) or any terminal with video RAM. The program writes
+ * characters straight into it and reads them back, it has no registers.
+ *
+ * The codes go through a character set to be shown:
+ *
+ *   ascii   20-7E as they are, control codes blank, bit 7 reverses the character
+ *   c64     C64 screen codes, upper case and graphics: 00 @, 01-1A A-Z, 20-3F as ASCII, 40-7F
+ *           graphics (the closest unicode), bit 7 reverses
+ *   apple2  Apple II: 00-3F inverse, 40-7F flashing, 80-FF normal (E0-FF lower case, as the IIe)
+ *
+ * On a terminal the screen is drawn in a box, at the top left corner unless told where, when it
+ * changes and at most every 20ms of wall clock, only the rows that changed. Whatever the host
+ * side wants to check is there as text.
+ */
+
+use std::io::{stdout, Write};
+use std::time::{Duration, Instant};
+use termion::{cursor, style};
+use devices::Device;
+
+const REFRESH: u64 = 20;        // ms
+const POLL: u64 = 10_000;       // cycles between looks at the wall clock, with something to show
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Charset {
+    Ascii,
+    C64,
+    Apple2,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Look {
+    Normal,
+    Reverse,
+    Flash,
+}
+
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    charset: Charset,
+    m: Vec<u8>,
+    show: Option<(Box<dyn Write>, u16, u16)>,   // and the terminal column and row of the box, from 1
+    framed: bool,       // the box is drawn
+    dirty: Vec<bool>,   // rows
+    since: Instant,     // last time shown
+    dump: bool,
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize, charset: Charset) -> Screen {
+        let blank = if charset == Charset::Apple2 { 0xA0 } else { 0x20 };
+        Screen {
+            cols,
+            rows,
+            charset,
+            m: vec![blank; cols * rows],
+            show: None,
+            framed: false,
+            dirty: vec![false; rows],
+            since: Instant::now(),
+            dump: false,
+        }
+    }
+
+    // bytes of memory it takes
+    pub fn len(&self) -> usize {
+        self.m.len()
+    }
+
+    // draw it on out, the box at terminal column x, row y
+    pub fn show(&mut self, out: Box<dyn Write>, x: u16, y: u16) {
+        self.show = Some((out, x, y));
+        self.framed = false;
+        self.dirty = vec![true; self.rows];
+    }
+
+    // print the screen as text when it goes away, at the end of the run
+    pub fn dump_at_exit(&mut self) {
+        self.dump = true;
+    }
+
+    // the screen as text, row by row, as it is shown but for the reversed and flashing
+    pub fn text(&self) -> Vec<String> {
+        self.m.chunks(self.cols).map(|row| row.iter().map(|&v| glyph(self.charset, v).0).collect()).collect()
+    }
+
+    fn dirty(&self) -> bool {
+        self.show.is_some() && self.dirty.iter().any(|&d| d)
+    }
+
+    fn draw(&mut self) {
+        let mut s = format!("{}", cursor::Save);
+        let (x, y) = match self.show {
+            Some((_, x, y)) => (x, y),
+            None => return,
+        };
+        if !self.framed {
+            let border = "-".repeat(self.cols);
+            s.push_str(&format!("{}+{}+", cursor::Goto(x, y), border));
+            for r in 0..self.rows {
+                let row = y + 1 + r as u16;
+                s.push_str(&format!("{}|{}|", cursor::Goto(x, row), cursor::Goto(x + 1 + self.cols as u16, row)));
+            }
+            s.push_str(&format!("{}+{}+", cursor::Goto(x, y + 1 + self.rows as u16), border));
+            self.framed = true;
+        }
+        for r in 0..self.rows {
+            if !self.dirty[r] {
+                continue;
+            }
+            s.push_str(&format!("{}", cursor::Goto(x + 1, y + 1 + r as u16)));
+            let mut look = Look::Normal;
+            for &v in self.m[r * self.cols..(r + 1) * self.cols].iter() {
+                let (ch, l) = glyph(self.charset, v);
+                if l != look {
+                    s.push_str(&format!("{}", style::Reset));
+                    match l {
+                        Look::Reverse => s.push_str(&format!("{}", style::Invert)),
+                        Look::Flash => s.push_str(&format!("{}", style::Blink)),
+                        Look::Normal => {}
+                    }
+                    look = l;
+                }
+                s.push(ch);
+            }
+            s.push_str(&format!("{}", style::Reset));
+            self.dirty[r] = false;
+        }
+        s.push_str(&format!("{}", cursor::Restore));
+        if let Some((ref mut out, _, _)) = self.show {
+            let _ = out.write_all(s.as_bytes());
+            let _ = out.flush();
+        }
+        self.since = Instant::now();
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        if self.dirty() {
+            self.draw();
+        }
+        if self.dump {
+            let mut out = stdout();
+            for line in self.text() {
+                let _ = write!(out, "{}\r\n", line.trim_end());
+            }
+            let _ = out.flush();
+        }
+    }
+}
+
+impl Device for Screen {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.m[reg as usize]
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        let i = reg as usize;
+        if self.m[i] != v {
+            self.m[i] = v;
+            self.dirty[i / self.cols] = true;
+        }
+    }
+    fn tick(&mut self, _cycles: u64) {
+        if self.dirty() && self.since.elapsed() >= Duration::from_millis(REFRESH) {
+            self.draw();
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.dirty() { Some(POLL) } else { None }
+    }
+}
+
+// C64 screen codes 40-7F, upper case and graphics set
+const C64_GRAPHICS: &str = "─♠│────││╮╰╯└╲╱┌┐●▁♥▏╭╳○♣▕♦┼▒│π◥ ▌▄▔▁▏▒▕▒◤▕├▗└┐▂┌┴┬┤▎▍▕▔▀▃┘▖▝┘▘▚";
+
+fn glyph(charset: Charset, v: u8) -> (char, Look) {
+    match charset {
+        Charset::Ascii => {
+            let look = if v & 0x80 != 0 { Look::Reverse } else { Look::Normal };
+            match v & 0x7F {
+                c @ 0x20..=0x7E => (c as char, look),
+                _ => (' ', look),
+            }
+        }
+        Charset::C64 => {
+            let look = if v & 0x80 != 0 { Look::Reverse } else { Look::Normal };
+            let ch = match v & 0x7F {
+                0x00 => '@',
+                c @ 0x01..=0x1A => (b'A' + c - 1) as char,
+                0x1B => '[',
+                0x1C => '\u{a3}',
+                0x1D => ']',
+                0x1E => '\u{2191}',
+                0x1F => '\u{2190}',
+                c @ 0x20..=0x3F => c as char,
+                c => C64_GRAPHICS.chars().nth(c as usize - 0x40).unwrap_or('?'),
+            };
+            (ch, look)
+        }
+        Charset::Apple2 => {
+            let look = match v >> 6 {
+                0 => Look::Reverse,
+                1 => Look::Flash,
+                _ => Look::Normal,
+            };
+            let ch = match v {
+                0x00..=0x7F => match v & 0x3F {
+                    c @ 0x00..=0x1F => (c + 0x40) as char,
+                    c => c as char,
+                },
+                0x80..=0x9F => (v - 0x40) as char,
+                _ => (v & 0x7F) as char,
+            };
+            (ch, look)
+        }
+    }
+}
+
+// for the command line: size=colsxrows (40x25), charset=ascii|c64|apple2, at=column,row, dump
+pub fn create(opts: &[&str]) -> Result<Screen, String> {
+    let (mut cols, mut rows) = (40, 25);
+    let mut charset = Charset::Ascii;
+    let mut at = (1, 1);
+    let mut dump = false;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("dump"), None) => dump = true,
+            (Some("size"), Some(v)) => {
+                let size: Vec<usize> = v.split('x').filter_map(|n| n.parse().ok()).collect();
+                match size[..] {
+                    [c, r] if c > 0 && r > 0 && c <= 255 && r <= 255 && c * r <= 65536 => {
+                        cols = c;
+                        rows = r;
+                    }
+                    _ => return Err(format!("bad screen size {}", v)),
+                }
+            }
+            (Some("charset"), Some("ascii")) => charset = Charset::Ascii,
+            (Some("charset"), Some("c64")) => charset = Charset::C64,
+            (Some("charset"), Some("apple2")) => charset = Charset::Apple2,
+            (Some("at"), Some(v)) => {
+                let pos: Vec<u16> = v.split(',').filter_map(|n| n.parse().ok()).collect();
+                match pos[..] {
+                    [x, y] if x > 0 && y > 0 => at = (x, y),
+                    _ => return Err(format!("bad screen position {}", v)),
+                }
+            }
+            _ => return Err(format!("unknown screen option {}", o)),
+        }
+    }
+    let mut screen = Screen::new(cols, rows, charset);
+    if ::termion::is_tty(&stdout()) {
+        screen.show(Box::new(stdout()), at.0, at.1);
+    }
+    if dump {
+        screen.dump_at_exit();
+    }
+    Ok(screen)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use devices::Device;
+    use devices::screen::*;
+
+    fn poke(screen: &mut Screen, at: u16, s: &[u8]) {
+        for (i, &v) in s.iter().enumerate() {
+            screen.write(at + i as u16, v);
+        }
+    }
+
+    #[test]
+    fn ascii() {
+        let mut screen = Screen::new(4, 2, Charset::Ascii);
+        assert_eq!(screen.len(), 8);
+        poke(&mut screen, 0, b"ab\x07~");
+        poke(&mut screen, 4, &[0xC1, 0x80]);
+        assert_eq!(screen.text(), ["ab ~", "A   "]);
+        assert!(glyph(Charset::Ascii, 0xC1).1 == Look::Reverse);
+    }
+
+    #[test]
+    fn c64_screen_codes() {
+        assert_eq!(C64_GRAPHICS.chars().count(), 64);
+        let mut screen = Screen::new(8, 1, Charset::C64);
+        poke(&mut screen, 0, &[0x08, 0x09, 0x21, 0x00, 0x1C, 0x41, 0xBF, 0x5E]);
+        assert_eq!(screen.text(), ["HI!@\u{a3}\u{2660}?\u{3c0}"]);
+    }
+
+    #[test]
+    fn apple2_blank_and_looks() {
+        let mut screen = Screen::new(6, 1, Charset::Apple2);
+        assert_eq!(screen.text(), ["      "]);
+        poke(&mut screen, 0, &[0xC8, 0xE9, 0x01, 0x41, 0x21, 0x9B]);
+        assert_eq!(screen.text(), ["HiAA!["]);
+        let looks: Vec<Look> = [0xC8, 0x01, 0x41].iter().map(|&v| glyph(Charset::Apple2, v).1).collect();
+        assert!(looks == [Look::Normal, Look::Reverse, Look::Flash]);
+    }
+
+    #[test]
+    fn redrawn_when_shown_and_changed() {
+        let mut screen = Screen::new(4, 2, Charset::Ascii);
+        screen.write(0, b'x');
+        assert_eq!(screen.next_event(), None);
+        screen.show(Box::new(io::sink()), 1, 1);
+        assert!(screen.next_event().is_some());
+        screen.draw();
+        assert_eq!(screen.next_event(), None);
+        screen.write(0, b'x');          // no change
+        assert_eq!(screen.next_event(), None);
+        screen.write(5, b'y');
+        assert!(screen.dirty == [false, true]);
+    }
+}
@@ -0,0 +1,351 @@
+/*
+ * An SD card in SPI mode, on a host image file. It is an SDHC card: block addressing, 512 bytes.
+ *
+ * The card starts in SD mode and only listens to CMD0, which must come with its CRC (95) and puts
+ * it in SPI mode, idle. From there the usual bring up: CMD8 (CRC checked too, 87 for arg 1AA), then
+ * CMD55 + ACMD41 until R1 says no longer idle, which takes two rounds here. Then:
+ *
+ *   CMD16  block length, 512 only
+ *   CMD17  read a block: R1, a byte of wait, FE, 512 bytes and their CRC16
+ *   CMD24  write a block: R1, then the host sends FE, 512 bytes and 2 CRC bytes (not checked).
+ *          Data response 05, then the card is busy (00) for a few bytes
+ *   CMD58  OCR, CCS set once ready
+ *
+ * Responses come after one byte of FF. Blocks past the end read as an out of range error token,
+ * and can't be written. Anything else is an illegal command. Deselecting forgets the command or
+ * the block being sent. No multi block transfers, no CSD or CID.
+ */
+
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use devices::spi::SpiDevice;
+
+const BLOCK: usize = 512;
+const ACMD41_ROUNDS: u8 = 2;
+const WRITE_BUSY: usize = 8;        // bytes
+
+// R1 bits
+const IDLE: u8 = 0x01;
+const ILLEGAL: u8 = 0x04;
+const CRC_ERROR: u8 = 0x08;
+const PARAMETER: u8 = 0x40;
+
+enum State {
+    Command,
+    WriteToken(u32),            // waiting for the data token of a block
+    WriteData(u32, Vec<u8>),
+}
+
+pub struct SdCard {
+    image: File,
+    readonly: bool,
+    blocks: u32,
+    spi_mode: bool,
+    idle: bool,
+    app: bool,                  // CMD55 came, the next is an application command
+    rounds: u8,                 // ACMD41 seen
+    command: Vec<u8>,
+    state: State,
+    out: VecDeque<u8>,
+}
+
+fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &b in data {
+        for i in (0..8).rev() {
+            let bit = (b >> i & 1) ^ (crc >> 6 & 1);
+            crc = crc << 1 & 0x7F;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &b in data {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { crc << 1 ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+impl SdCard {
+    pub fn new(image: File, readonly: bool) -> Result<SdCard, String> {
+        let len = image.metadata().map_err(|e| e.to_string())?.len();
+        Ok(SdCard {
+            image,
+            readonly,
+            blocks: (len / BLOCK as u64).min(0xFFFF_FFFF) as u32,
+            spi_mode: false,
+            idle: true,
+            app: false,
+            rounds: 0,
+            command: Vec::new(),
+            state: State::Command,
+            out: VecDeque::new(),
+        })
+    }
+
+    pub fn open(file: &str, readonly: bool) -> Result<SdCard, String> {
+        let image = OpenOptions::new().read(true).write(!readonly).open(file).map_err(|e| format!("{}: {}", file, e))?;
+        SdCard::new(image, readonly)
+    }
+
+    fn respond(&mut self, r: &[u8]) {
+        self.out.push_back(0xFF);
+        self.out.extend(r.iter().cloned());
+    }
+
+    fn r1(&self) -> u8 {
+        self.idle as u8
+    }
+
+    fn execute(&mut self) {
+        let c = self.command.split_off(0);
+        let index = c[0] & 0x3F;
+        let arg = (c[1] as u32) << 24 | (c[2] as u32) << 16 | (c[3] as u32) << 8 | c[4] as u32;
+        let crc_ok = crc7(&c[0..5]) << 1 | 1 == c[5];
+        if !self.spi_mode {
+            if index == 0 && crc_ok {
+                self.spi_mode = true;
+                self.respond(&[IDLE]);
+            }
+            return;
+        }
+        let app = self.app;
+        self.app = false;
+        match (app, index) {
+            (_, 0) if !crc_ok => self.respond(&[IDLE | CRC_ERROR]),
+            (_, 0) => {
+                self.idle = true;
+                self.rounds = 0;
+                self.respond(&[IDLE]);
+            }
+            (false, 8) if !crc_ok => self.respond(&[IDLE | CRC_ERROR]),
+            (false, 8) => {
+                let r1 = self.r1();
+                self.respond(&[r1, 0x00, 0x00, c[3] & 0x0F, c[4]]);
+            }
+            (false, 55) => {
+                self.app = true;
+                let r1 = self.r1();
+                self.respond(&[r1]);
+            }
+            (true, 41) => {
+                self.rounds = self.rounds.saturating_add(1);
+                if self.rounds >= ACMD41_ROUNDS {
+                    self.idle = false;
+                }
+                let r1 = self.r1();
+                self.respond(&[r1]);
+            }
+            (false, 58) => {
+                let r1 = self.r1();
+                let status = if self.idle { 0x00 } else { 0xC0 };
+                self.respond(&[r1, status, 0xFF, 0x80, 0x00]);
+            }
+            (false, 16) if !self.idle => self.respond(&[if arg == BLOCK as u32 { 0 } else { PARAMETER }]),
+            (false, 17) if !self.idle => {
+                if arg >= self.blocks {
+                    self.respond(&[0x00, 0xFF, 0x08]);
+                    return;
+                }
+                let mut data = vec![0u8; BLOCK];
+                let read = self.image.seek(SeekFrom::Start(arg as u64 * BLOCK as u64))
+                    .and_then(|_| self.image.read_exact(&mut data));
+                if read.is_err() {
+                    self.respond(&[0x00, 0xFF, 0x01]);     // error token, generic
+                    return;
+                }
+                let crc = crc16(&data);
+                self.respond(&[0x00, 0xFF, 0xFE]);
+                self.out.extend(data);
+                self.out.extend(&[(crc >> 8) as u8, crc as u8]);
+            }
+            (false, 24) if !self.idle => {
+                if arg >= self.blocks || self.readonly {
+                    self.respond(&[PARAMETER]);
+                } else {
+                    self.respond(&[0x00]);
+                    self.state = State::WriteToken(arg);
+                }
+            }
+            _ => {
+                let r1 = self.r1();
+                self.respond(&[r1 | ILLEGAL]);
+            }
+        }
+    }
+
+    fn write_block(&mut self, block: u32, data: &[u8]) {
+        let written = self.image.seek(SeekFrom::Start(block as u64 * BLOCK as u64))
+            .and_then(|_| self.image.write_all(&data[..BLOCK]))
+            .and_then(|_| self.image.flush());
+        self.out.push_back(if written.is_ok() { 0x05 } else { 0x0D });
+        self.out.extend(&[0x00; WRITE_BUSY]);
+    }
+}
+
+impl SpiDevice for SdCard {
+    fn select(&mut self, selected: bool) {
+        if !selected {
+            self.command.clear();
+            self.out.clear();
+            self.state = State::Command;
+        }
+    }
+    fn output(&self) -> u8 {
+        self.out.front().cloned().unwrap_or(0xFF)
+    }
+    fn input(&mut self, v: u8) {
+        self.out.pop_front();
+        match self.state {
+            State::Command => {
+                if self.command.is_empty() && v & 0xC0 != 0x40 {
+                    return;         // filler between commands
+                }
+                self.command.push(v);
+                if self.command.len() == 6 {
+                    self.execute();
+                }
+            }
+            State::WriteToken(block) => {
+                if v == 0xFE {
+                    self.state = State::WriteData(block, Vec::with_capacity(BLOCK + 2));
+                }
+            }
+            State::WriteData(block, ref mut data) => {
+                data.push(v);
+                if data.len() < BLOCK + 2 {
+                    return;
+                }
+                let data = data.split_off(0);
+                self.state = State::Command;
+                self.write_block(block, &data);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use devices::spi::Spi;
+    use devices::sdcard::*;
+
+    // a card of 4 blocks on select 0, block n full of n
+    fn card(name: &str, readonly: bool) -> (String, Spi) {
+        // tests run in parallel, and maybe more runs at once
+        let name = format!("hell65-{}-{}", ::std::process::id(), name);
+        let path = ::std::env::temp_dir().join(name).to_string_lossy().into_owned();
+        fs::write(&path, (0..4 * BLOCK).map(|i| (i / BLOCK) as u8).collect::<Vec<u8>>()).unwrap();
+        let image = OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut spi = Spi::new();
+        spi.attach(0, Box::new(SdCard::new(image, readonly).unwrap()));
+        spi.set_select(0xFE);
+        (path, spi)
+    }
+
+    // a command, then the response bytes as firmware polls for them
+    fn command(spi: &mut Spi, index: u8, arg: u32, len: usize) -> Vec<u8> {
+        let mut c = vec![0x40 | index, (arg >> 24) as u8, (arg >> 16) as u8, (arg >> 8) as u8, arg as u8];
+        let crc = crc7(&c) << 1 | 1;
+        c.push(crc);
+        for &b in &c {
+            assert_eq!(spi.transfer(b), 0xFF);
+        }
+        let mut r = vec![0xFF];
+        for _ in 0..8 {
+            r[0] = spi.transfer(0xFF);
+            if r[0] != 0xFF {
+                break;
+            }
+        }
+        r.extend((1..len).map(|_| spi.transfer(0xFF)));
+        r
+    }
+
+    fn bring_up(spi: &mut Spi) {
+        assert_eq!(command(spi, 0, 0, 1), [0x01]);
+        assert_eq!(command(spi, 8, 0x1AA, 5), [0x01, 0x00, 0x00, 0x01, 0xAA]);
+        assert_eq!(command(spi, 58, 0, 5), [0x01, 0x00, 0xFF, 0x80, 0x00]);
+        assert_eq!(command(spi, 55, 0, 1), [0x01]);
+        assert_eq!(command(spi, 41, 0x4000_0000, 1), [0x01]);
+        assert_eq!(command(spi, 55, 0, 1), [0x01]);
+        assert_eq!(command(spi, 41, 0x4000_0000, 1), [0x00]);
+        assert_eq!(command(spi, 58, 0, 5), [0x00, 0xC0, 0xFF, 0x80, 0x00]);
+    }
+
+    #[test]
+    fn crcs() {
+        assert_eq!(crc7(&[0x40, 0, 0, 0, 0]) << 1 | 1, 0x95);
+        assert_eq!(crc7(&[0x48, 0, 0, 0x01, 0xAA]) << 1 | 1, 0x87);
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+    }
+
+    #[test]
+    fn only_cmd0_with_its_crc_wakes_it_up() {
+        let (path, mut spi) = card("sd-wake.img", false);
+        for &b in &[0x40, 0, 0, 0, 0, 0x01, 0xFF, 0xFF, 0xFF] {
+            assert_eq!(spi.transfer(b), 0xFF);
+        }
+        assert_eq!(command(&mut spi, 17, 0, 1), [0xFF]);
+        bring_up(&mut spi);
+        assert_eq!(command(&mut spi, 16, 512, 1), [0x00]);
+        assert_eq!(command(&mut spi, 16, 1024, 1), [PARAMETER]);
+        assert_eq!(command(&mut spi, 9, 0, 1), [ILLEGAL]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reads_a_block() {
+        let (path, mut spi) = card("sd-read.img", false);
+        bring_up(&mut spi);
+        let r = command(&mut spi, 17, 2, 3 + BLOCK + 2);
+        assert_eq!(r[..3], [0x00, 0xFF, 0xFE]);
+        assert!(r[3..3 + BLOCK].iter().all(|&b| b == 2));
+        let crc = crc16(&r[3..3 + BLOCK]);
+        assert_eq!(r[3 + BLOCK..], [(crc >> 8) as u8, crc as u8]);
+        assert_eq!(command(&mut spi, 17, 4, 3), [0x00, 0xFF, 0x08]);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn writes_a_block() {
+        let (path, mut spi) = card("sd-write.img", false);
+        bring_up(&mut spi);
+        assert_eq!(command(&mut spi, 24, 1, 1), [0x00]);
+        spi.transfer(0xFF);
+        spi.transfer(0xFE);
+        for i in 0..BLOCK + 2 {
+            spi.transfer(i as u8);
+        }
+        assert_eq!(spi.transfer(0xFF), 0x05);
+        let busy = (0..20).take_while(|_| spi.transfer(0xFF) == 0x00).count();
+        assert_eq!(busy, WRITE_BUSY);
+        let data = fs::read(&path).unwrap();
+        assert!((0..BLOCK).all(|i| data[BLOCK + i] == i as u8));
+        assert_eq!(data[2 * BLOCK], 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn read_only_and_deselect() {
+        let (path, mut spi) = card("sd-ro.img", true);
+        bring_up(&mut spi);
+        assert_eq!(command(&mut spi, 24, 0, 1), [PARAMETER]);
+        for &b in &[0x51, 0, 0] {
+            spi.transfer(b);
+        }
+        spi.set_select(0xFF);           // half a command, forgotten
+        spi.set_select(0xFE);
+        assert_eq!(command(&mut spi, 58, 0, 1), [0x00]);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+/*
+ * SPI, for SD cards, flash chips and the like. The bus has up to 8 slaves, each on its own
+ * active low select line, and moves bytes MSB first, full duplex. MISO is pulled up: nobody
+ * selected reads FF, more slaves selected are ANDed like open drain outputs.
+ *
+ * Masters drive the bus a byte at a time, or bit bang it in mode 0 (CPOL 0, CPHA 0): MOSI sampled
+ * on the rising edge of SCK, MISO moves on the falling one, e.g. through a VIA port (see via.rs).
+ *
+ * Controller, a master in the style of the 65SPI:
+ *   0  data: write sends the byte, read gets the one received. Both clear complete
+ *   1  status, read: bit 7 complete, bit 6 busy, bit 4 fast receive
+ *      control, write: bit 7 interrupt on complete, bit 4 fast receive (reading data sends FF)
+ *   2  clock divisor: a byte takes 16 * (divisor + 1) cycles
+ *   3  select lines, active low, FF at reset
+ * The byte moves at once and the received one is there right away, only complete and busy wait
+ * for the clock. Modes other than 0 are not there, nor is the external clock.
+ */
+
+use devices::Device;
+use devices::sdcard;
+
+// a slave on the bus
+pub trait SpiDevice {
+    // select line went low (true) or high
+    fn select(&mut self, _selected: bool) {}
+    // the byte going out with the next one in. Only input moves things on
+    fn output(&self) -> u8;
+    // the byte shifted in, the output byte went with it
+    fn input(&mut self, v: u8);
+}
+
+pub struct Spi {
+    slaves: Vec<Option<Box<dyn SpiDevice>>>,
+    lines: u8,              // select lines, low selects
+    sck: bool,
+    bits: u8,               // of the byte being banged
+    shift_in: u8,
+}
+
+impl Spi {
+    pub fn new() -> Spi {
+        Spi { slaves: (0..8).map(|_| None).collect(), lines: 0xFF, sck: false, bits: 0, shift_in: 0 }
+    }
+
+    pub fn attach(&mut self, line: usize, dev: Box<dyn SpiDevice>) {
+        self.slaves[line] = Some(dev);
+    }
+
+    pub fn set_select(&mut self, lines: u8) {
+        let changed = self.lines ^ lines;
+        self.lines = lines;
+        if changed != 0 {
+            // a new frame, whatever was half banged is gone
+            self.bits = 0;
+        }
+        for (i, s) in self.slaves.iter_mut().enumerate() {
+            if let Some(ref mut s) = *s {
+                if changed & 1 << i != 0 {
+                    s.select(lines & 1 << i == 0);
+                }
+            }
+        }
+    }
+
+    // a whole byte each way
+    pub fn transfer(&mut self, mosi: u8) -> u8 {
+        let lines = self.lines;
+        let mut miso = 0xFF;
+        for (i, s) in self.slaves.iter_mut().enumerate() {
+            if let Some(ref mut s) = *s {
+                if lines & 1 << i == 0 {
+                    miso &= s.output();
+                    s.input(mosi);
+                }
+            }
+        }
+        miso
+    }
+
+    // bit banging: the pins as the master drives them, MISO back
+    pub fn clock(&mut self, sck: bool, mosi: bool) -> bool {
+        if sck && !self.sck {
+            self.shift_in = self.shift_in << 1 | mosi as u8;
+        } else if !sck && self.sck {
+            self.bits += 1;
+            if self.bits == 8 {
+                let v = self.shift_in;
+                let lines = self.lines;
+                for (i, s) in self.slaves.iter_mut().enumerate() {
+                    if let Some(ref mut s) = *s {
+                        if lines & 1 << i == 0 {
+                            s.input(v);
+                        }
+                    }
+                }
+                self.bits = 0;
+            }
+        }
+        self.sck = sck;
+        self.miso()
+    }
+
+    fn miso(&self) -> bool {
+        let mut v = 0xFF;
+        for (i, s) in self.slaves.iter().enumerate() {
+            if let Some(ref s) = *s {
+                if self.lines & 1 << i == 0 {
+                    v &= s.output();
+                }
+            }
+        }
+        v & 0x80 >> self.bits != 0
+    }
+}
+
+const COMPLETE: u8 = 0x80;
+const BUSY: u8 = 0x40;
+const FAST: u8 = 0x10;
+
+pub struct Controller {
+    spi: Spi,
+    data: u8,
+    control: u8,
+    complete: bool,
+    divisor: u8,
+    busy: u64,              // cycles to complete
+}
+
+impl Controller {
+    pub fn new(spi: Spi) -> Controller {
+        Controller { spi, data: 0xFF, control: 0, complete: false, divisor: 0, busy: 0 }
+    }
+
+    fn send(&mut self, v: u8) {
+        self.data = self.spi.transfer(v);
+        self.complete = false;
+        self.busy = 16 * (self.divisor as u64 + 1);
+    }
+
+    fn register(&self, reg: u16) -> u8 {
+        match reg & 3 {
+            0 => self.data,
+            1 => (self.complete as u8) << 7 | if self.busy > 0 { BUSY } else { 0 } | self.control & FAST,
+            2 => self.divisor,
+            _ => self.spi.lines,
+        }
+    }
+}
+
+impl Device for Controller {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        if reg & 3 == 0 {
+            self.complete = false;
+            if self.control & FAST != 0 && self.busy == 0 {
+                self.send(0xFF);
+            }
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 3 {
+            0 => if self.busy == 0 { self.send(v) },
+            1 => self.control = v & (COMPLETE | FAST),
+            2 => self.divisor = v,
+            _ => self.spi.set_select(v),
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, cycles: u64) {
+        if self.busy > 0 && cycles >= self.busy {
+            self.complete = true;
+        }
+        self.busy = self.busy.saturating_sub(cycles);
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.busy > 0 { Some(self.busy) } else { None }
+    }
+    fn irq(&self) -> bool {
+        self.complete && self.control & COMPLETE != 0
+    }
+}
+
+// for the command line: sd=image for an SD card on select 0, ro for a write protected one
+pub fn create(opts: &[&str]) -> Result<Controller, String> {
+    let mut spi = Spi::new();
+    let mut file = None;
+    let mut readonly = false;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("sd"), Some(f)) => file = Some(f),
+            (Some("ro"), None) => readonly = true,
+            _ => return Err(format!("unknown spi option {}", o)),
+        }
+    }
+    if let Some(f) = file {
+        spi.attach(0, Box::new(sdcard::SdCard::open(f, readonly)?));
+    }
+    Ok(Controller::new(spi))
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::spi::*;
+
+    // sends back what it got, a byte late
+    struct Echo(u8);
+
+    impl SpiDevice for Echo {
+        fn output(&self) -> u8 {
+            self.0
+        }
+        fn input(&mut self, v: u8) {
+            self.0 = v;
+        }
+    }
+
+    #[test]
+    fn controller_completes_on_the_clock() {
+        let mut spi = Spi::new();
+        spi.attach(1, Box::new(Echo(0x5A)));
+        let mut c = Controller::new(spi);
+        c.write(3, 0xFD);
+        c.write(2, 1);
+        c.write(1, COMPLETE);
+        c.write(0, 0x42);
+        assert_eq!(c.read(1), BUSY);
+        assert_eq!(c.next_event(), Some(32));
+        c.write(0, 0x99);                   // busy, ignored
+        c.tick(32);
+        assert!(c.irq());
+        assert_eq!(c.read(0), 0x5A);
+        assert!(!c.irq());
+        c.write(1, FAST);
+        c.write(0, 0x17);
+        c.tick(32);
+        assert_eq!(c.read(0), 0x42);        // and sends FF
+        c.tick(32);
+        assert_eq!(c.read(0), 0x17);
+        c.write(3, 0xFF);
+        c.tick(32);
+        assert_eq!(c.read(0), 0xFF);        // nobody there
+    }
+
+    #[test]
+    fn bit_banged_in_mode_0() {
+        let mut spi = Spi::new();
+        spi.attach(0, Box::new(Echo(0xA5)));
+        spi.set_select(0xFE);
+        let mut miso = 0u8;
+        for i in (0..8).rev() {
+            miso = miso << 1 | spi.clock(false, 0x3C >> i & 1 != 0) as u8;
+            spi.clock(true, 0x3C >> i & 1 != 0);
+        }
+        spi.clock(false, false);
+        assert_eq!(miso, 0xA5);
+        assert_eq!(spi.transfer(0), 0x3C);
+    }
+}
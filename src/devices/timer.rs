@@ -0,0 +1,178 @@
+/*
+ * A programmable interval timer, for interrupt driven code without a VIA driver.
+ *
+ *   0-3  period in cycles, little endian, on write. Cycles to the next expiry on read
+ *   4    control: bit 0 run, bit 1 periodic (else one shot), bit 2 NMI (else IRQ)
+ *   5    status, read: bit 0 running, bit 7 expired. Any write acknowledges, releasing the line
+ *
+ * Starting loads the period, and the timer expires that many cycles later. Periodic timers
+ * reload and go on, one shot ones stop. The interrupt line is asserted from the expiry to the
+ * acknowledge. A period of 0 doesn't start, and a periodic timer reloading 0 stops.
+ */
+
+use devices::Device;
+
+pub struct Timer {
+    period: u32,
+    counter: u32,       // cycles to expiry
+    control: u8,
+    expired: bool,
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer { period: 0, counter: 0, control: 0, expired: false }
+    }
+
+    // already going at power on, for the command line
+    pub fn running(period: u32, nmi: bool) -> Timer {
+        let mut t = Timer::new();
+        t.period = period;
+        t.write(4, 0x03 | (nmi as u8) << 2);
+        t
+    }
+
+    fn run(&self) -> bool {
+        self.control & 0x01 != 0
+    }
+    fn periodic(&self) -> bool {
+        self.control & 0x02 != 0
+    }
+    fn to_nmi(&self) -> bool {
+        self.control & 0x04 != 0
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, reg: u16) -> u8 {
+        match reg {
+            0..=3 => (self.counter >> (8 * reg)) as u8,
+            4 => self.control,
+            _ => self.run() as u8 | (self.expired as u8) << 7,
+        }
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg {
+            0..=3 => {
+                let shift = 8 * reg;
+                self.period = self.period & !(0xFF << shift) | (v as u32) << shift;
+            }
+            4 => {
+                let start = v & 0x01 != 0 && !self.run();
+                self.control = v & 0x07;
+                if start {
+                    if self.period == 0 {
+                        self.control &= !0x01;
+                    } else {
+                        self.counter = self.period;
+                    }
+                }
+            }
+            _ => self.expired = false,
+        }
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while self.run() && cycles >= self.counter as u64 {
+            cycles -= self.counter as u64;
+            self.expired = true;
+            if self.periodic() && self.period > 0 {
+                self.counter = self.period;
+            } else {
+                self.control &= !0x01;
+                self.counter = 0;
+                return;
+            }
+        }
+        if self.run() {
+            self.counter -= cycles as u32;
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.run() { Some(self.counter as u64) } else { None }
+    }
+    fn irq(&self) -> bool {
+        self.expired && !self.to_nmi()
+    }
+    fn nmi(&self) -> bool {
+        self.expired && self.to_nmi()
+    }
+}
+
+// for the command line: period=cycles starts it periodic at power on, nmi
+pub fn create(opts: &[&str]) -> Result<Timer, String> {
+    let mut period = 0;
+    let mut nmi = false;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("nmi"), None) => nmi = true,
+            (Some("period"), Some(v)) => {
+                period = v.parse::<u32>().ok().and_then(|v| if v > 0 { Some(v) } else { None })
+                          .ok_or(format!("bad period {}", v))?;
+            }
+            _ => return Err(format!("unknown timer option {}", o)),
+        }
+    }
+    if period > 0 {
+        Ok(Timer::running(period, nmi))
+    } else {
+        let mut t = Timer::new();
+        t.control = (nmi as u8) << 2;
+        Ok(t)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::timer::*;
+
+    fn period(t: &mut Timer, p: u32) {
+        for i in 0..4 {
+            t.write(i, (p >> (8 * i)) as u8);
+        }
+    }
+
+    #[test]
+    fn one_shot() {
+        let mut t = Timer::new();
+        period(&mut t, 10);
+        t.write(4, 0x01);
+        assert_eq!(t.next_event(), Some(10));
+        t.tick(9);
+        assert_eq!(t.read(0), 1);
+        assert!(!t.irq());
+        t.tick(1);
+        assert!(t.irq() && !t.nmi());
+        assert_eq!(t.read(5), 0x80);
+        assert_eq!(t.next_event(), None);
+        t.tick(100);
+        assert_eq!(t.read(5), 0x80);
+    }
+
+    #[test]
+    fn periodic_until_acknowledged() {
+        let mut t = Timer::running(10, true);
+        t.tick(25);
+        assert!(t.nmi() && !t.irq());
+        assert_eq!(t.read(5), 0x81);
+        assert_eq!(t.read(0), 5);
+        t.write(5, 0);
+        assert!(!t.nmi());
+        t.tick(5);
+        assert!(t.nmi());
+    }
+
+    #[test]
+    fn zero_period() {
+        let mut t = Timer::new();
+        t.write(4, 0x03);
+        assert_eq!(t.read(5), 0);       // doesn't start
+        period(&mut t, 10);
+        t.write(4, 0x03);
+        period(&mut t, 0);              // the next reload stops it
+        t.tick(25);
+        assert_eq!(t.read(5), 0x80);
+        assert_eq!(t.next_event(), None);
+    }
+}
@@ -0,0 +1,485 @@
+/*
+ * TI TMS9918A video display processor, with its 16KB of VRAM, frames to image files.
+ *
+ * Two ports, MODE on A0:
+ *
+ *   0  data: VRAM at the address pointer, which goes up after every access. Reads come from a
+ *      read ahead buffer, refilled right after, writes go to the buffer too
+ *   1  control, write: two bytes. The first is latched; the second with bit 7 set writes the
+ *      first into register (bits 0-2), else it is the high 6 bits of the address, bit 6 set for
+ *      writing (a read setup fills the buffer). Any data access or status read resets the latch
+ *      status, read: bit 7 F frame done, bit 6 5S fifth sprite, bit 5 C coincidence, bits 0-4
+ *      the fifth sprite number. Reading clears the flags, and the interrupt
+ *
+ *   R0  bit 1 M3, bit 0 external video (ignored)
+ *   R1  bit 7 4/16K (always 16K), bit 6 display on, bit 5 interrupt enable, bit 4 M1, bit 3 M2,
+ *       bit 1 16x16 sprites, bit 0 magnified sprites
+ *   R2  name table * 400, R3 colour table * 40, R4 pattern generator * 800
+ *   R5  sprite attributes * 80, R6 sprite patterns * 800, R7 text colour / backdrop
+ *
+ * Modes: text (M1, 40x24 of 6x8, two colours from R7), graphics I (32x24, a colour byte every 8
+ * patterns), graphics II (M3, a pattern and colour table per third of the screen, the masks in R3
+ * and R4 as on the chip), multicolour (M2, 64x48 blocks of 4x4). Colour 0 is transparent, the
+ * backdrop shows through.
+ *
+ * 32 sprites, up to 4 on a line: a fifth sets 5S and its number, and isn't shown. A Y of D0 ends
+ * the list, E1-FF are above the top. Bit 7 of the colour shifts the sprite 32 pixels left. Two
+ * sprites with a pixel in the same place set C, transparent ones too. Lower numbers are in front.
+ *
+ * The picture is 256x192, no border, composed at once from VRAM at the end of the frame, when F
+ * goes up. So effects that change registers mid frame are not seen, and VRAM accesses are never
+ * too fast. A frame is 16667 cycles, 60Hz at 1MHz, unless told otherwise. Snapshots go to the
+ * file named with snap=, every frame or every nth. A # in the name is the number of the snapshot,
+ * 6 digits, else the same file is written over. One that can't be written stops the run with a
+ * device error.
+ */
+
+use cpu::BusFault;
+use devices::Device;
+use devices::image;
+
+const FRAME: u64 = 16667;       // cycles
+const WIDTH: usize = 256;
+const HEIGHT: usize = 192;
+
+const F: u8 = 0x80;
+const FIFTH: u8 = 0x40;
+const C: u8 = 0x20;
+
+// the usual RGB for the 15 colours, 0 transparent is black when it comes to it
+const PALETTE: [u32; 16] = [0x000000, 0x000000, 0x21C842, 0x5EDC78, 0x5455ED, 0x7D76FC, 0xD4524D, 0x42EBF5,
+                            0xFC5554, 0xFF7978, 0xD4C154, 0xE6CE80, 0x21B03B, 0xC95BBA, 0xCCCCCC, 0xFFFFFF];
+
+pub struct Vdp {
+    vram: Vec<u8>,
+    regs: [u8; 8],
+    address: u16,
+    buffer: u8,         // read ahead
+    latch: Option<u8>,  // first byte of a control write
+    status: u8,
+    picture: Vec<u8>,   // colour indexes, last frame composed
+    frame: u64,         // cycles
+    into: u64,          // cycles into the frame
+    frames: u64,
+    snap: Option<String>,
+    every: u64,         // frames
+    shots: u32,
+    fault: Option<BusFault>,
+}
+
+impl Vdp {
+    pub fn new() -> Vdp {
+        Vdp {
+            vram: vec![0; 16384],
+            regs: [0; 8],
+            address: 0,
+            buffer: 0,
+            latch: None,
+            status: 0,
+            picture: vec![0; WIDTH * HEIGHT],
+            frame: FRAME,
+            into: 0,
+            frames: 0,
+            snap: None,
+            every: 1,
+            shots: 0,
+            fault: None,
+        }
+    }
+
+    pub fn set_frame(&mut self, cycles: u64) {
+        self.frame = cycles.max(1);
+    }
+
+    // snapshots to file, numbered at #, every so many frames
+    pub fn snapshots(&mut self, file: &str, every: u64) {
+        self.snap = Some(file.to_string());
+        self.every = every.max(1);
+    }
+
+    // the last frame, 3 bytes of RGB a pixel
+    pub fn rgb(&self) -> Vec<u8> {
+        let mut v = Vec::with_capacity(WIDTH * HEIGHT * 3);
+        for &c in self.picture.iter() {
+            let rgb = PALETTE[c as usize];
+            v.extend_from_slice(&[(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8]);
+        }
+        v
+    }
+
+    fn interrupts(&self) -> bool {
+        self.regs[1] & 0x20 != 0
+    }
+
+    fn control(&mut self, v: u8) {
+        let first = match self.latch.take() {
+            None => {
+                self.latch = Some(v);
+                return;
+            }
+            Some(first) => first,
+        };
+        if v & 0x80 != 0 {
+            self.regs[v as usize & 7] = first;
+        } else {
+            self.address = (v as u16 & 0x3F) << 8 | first as u16;
+            if v & 0x40 == 0 {
+                self.read_ahead();
+            }
+        }
+    }
+
+    fn read_ahead(&mut self) {
+        self.buffer = self.vram[self.address as usize];
+        self.address = (self.address + 1) & 0x3FFF;
+    }
+
+    // the frame is over: compose the picture, raise F
+    fn end_of_frame(&mut self) {
+        self.compose();
+        self.status |= F;
+        self.frames += 1;
+        if self.snap.is_some() && self.frames.is_multiple_of(self.every) {
+            self.snapshot();
+        }
+    }
+
+    fn snapshot(&mut self) {
+        let name = match self.snap {
+            Some(ref s) => image::numbered(s, self.shots),
+            None => return,
+        };
+        self.shots += 1;
+        if image::save(&name, WIDTH, HEIGHT, &self.rgb()).is_err() {
+            self.fault = Some(BusFault::Device("snapshot not written"));
+        }
+    }
+
+    fn compose(&mut self) {
+        let backdrop = self.regs[7] & 0x0F;
+        if self.regs[1] & 0x40 == 0 {
+            // blanked, no sprites either
+            for p in self.picture.iter_mut() {
+                *p = backdrop;
+            }
+            return;
+        }
+        let text = self.regs[1] & 0x10 != 0;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let c = self.background(x, y);
+                self.picture[y * WIDTH + x] = if c == 0 { backdrop } else { c };
+            }
+            if !text {
+                self.sprites(y);
+            }
+        }
+    }
+
+    // colour of the pattern at x, y, 0 transparent
+    fn background(&self, x: usize, y: usize) -> u8 {
+        let r = &self.regs;
+        let vram = &self.vram;
+        let nt = (r[2] as usize & 0x0F) << 10;
+        let pg = (r[4] as usize & 0x07) << 11;
+        let pick = |pattern: u8, bit: usize, colour: u8| if pattern & 0x80 >> bit != 0 { colour >> 4 } else { colour & 0x0F };
+        if r[1] & 0x10 != 0 {
+            // text
+            if !(8..248).contains(&x) {
+                return 0;
+            }
+            let name = vram[nt + y / 8 * 40 + (x - 8) / 6] as usize;
+            return pick(vram[pg + name * 8 + y % 8], (x - 8) % 6, r[7]);
+        }
+        let name = vram[nt + y / 8 * 32 + x / 8] as usize;
+        if r[1] & 0x08 != 0 {
+            // multicolour
+            let b = vram[pg + name * 8 + (y / 8 % 4) * 2 + y % 8 / 4];
+            return if x % 8 < 4 { b >> 4 } else { b & 0x0F };
+        }
+        if r[0] & 0x02 != 0 {
+            // graphics II: the third of the screen picks the tables, the masks keep only some of it
+            let index = (((y / 64) << 8 | name) << 3) | (y % 8);
+            let pattern = vram[(r[4] as usize & 0x04) << 11 | index & ((r[4] as usize & 0x03) << 11 | 0x7FF)];
+            let colour = vram[(r[3] as usize & 0x80) << 6 | index & ((r[3] as usize & 0x7F) << 6 | 0x3F)];
+            return pick(pattern, x % 8, colour);
+        }
+        // graphics I
+        let ct = (r[3] as usize) << 6;
+        pick(vram[pg + name * 8 + y % 8], x % 8, vram[ct + name / 8])
+    }
+
+    // sprites on line y, over the picture, with the flags they raise
+    fn sprites(&mut self, y: usize) {
+        let sa = (self.regs[5] as usize & 0x7F) << 7;
+        let sp = (self.regs[6] as usize & 0x07) << 11;
+        let size = if self.regs[1] & 0x02 != 0 { 16 } else { 8 };
+        let mag = if self.regs[1] & 0x01 != 0 { 2 } else { 1 };
+        let mut taken = [false; WIDTH];     // a sprite pixel is there
+        let mut shown = [false; WIDTH];     // and its colour
+        let mut on_line = 0;
+        let mut last = 31;
+        for n in 0..32 {
+            let a = sa + n * 4;
+            let sy = self.vram[a];
+            if sy == 0xD0 {
+                last = n;
+                break;
+            }
+            let top = (if sy >= 0xE1 { sy as isize - 256 } else { sy as isize }) + 1;
+            let row = y as isize - top;
+            if row < 0 || row >= (size * mag) as isize {
+                continue;
+            }
+            on_line += 1;
+            if on_line == 5 {
+                if self.status & FIFTH == 0 {
+                    self.status = self.status & !0x1F | FIFTH | n as u8;
+                }
+                return;
+            }
+            let row = row as usize / mag;
+            let (sx, colour) = (self.vram[a + 1] as isize, self.vram[a + 3]);
+            let pattern = self.vram[a + 2] as usize & if size == 16 { 0xFC } else { 0xFF };
+            let left = sx - if colour & 0x80 != 0 { 32 } else { 0 };
+            for p in 0..size * mag {
+                let x = left + p as isize;
+                if x < 0 || x >= WIDTH as isize {
+                    continue;
+                }
+                let x = x as usize;
+                let c = p / mag;
+                // 16x16: the quadrants go down the left half, then the right one
+                let bits = self.vram[sp + pattern * 8 + c / 8 * 16 + row];
+                if bits & 0x80 >> (c % 8) == 0 {
+                    continue;
+                }
+                if taken[x] {
+                    self.status |= C;
+                }
+                taken[x] = true;
+                if !shown[x] && colour & 0x0F != 0 {
+                    shown[x] = true;
+                    self.picture[y * WIDTH + x] = colour & 0x0F;
+                }
+            }
+        }
+        if self.status & FIFTH == 0 {
+            self.status = self.status & !0x1F | last as u8;
+        }
+    }
+}
+
+impl Device for Vdp {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.latch = None;
+        if reg & 1 == 0 {
+            let v = self.buffer;
+            self.read_ahead();
+            v
+        } else {
+            let v = self.status;
+            self.status &= 0x1F;
+            v
+        }
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        if reg & 1 == 0 {
+            self.latch = None;
+            self.vram[self.address as usize] = v;
+            self.buffer = v;
+            self.address = (self.address + 1) & 0x3FFF;
+        } else {
+            self.control(v);
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        if reg & 1 == 0 { self.buffer } else { self.status }
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.into += cycles;
+        if self.into >= self.frame {
+            // frames the program couldn't look at are not composed
+            let skipped = self.into / self.frame - 1;
+            self.frames += skipped;
+            self.into %= self.frame;
+            self.end_of_frame();
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        if self.snap.is_some() || self.interrupts() && self.status & F == 0 {
+            Some(self.frame - self.into)
+        } else {
+            None
+        }
+    }
+    fn irq(&self) -> bool {
+        self.interrupts() && self.status & F != 0
+    }
+    fn fault(&mut self) -> Option<BusFault> {
+        self.fault.take()
+    }
+}
+
+// for the command line: frame=cycles (16667), snap=file (.png or .ppm, # numbered), every=frames (1)
+pub fn create(opts: &[&str]) -> Result<Vdp, String> {
+    let mut vdp = Vdp::new();
+    let mut snap = None;
+    let mut every = 1;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("frame"), Some(v)) => match v.parse() {
+                Ok(n) if n > 0 => vdp.set_frame(n),
+                _ => return Err(format!("bad tms9918 frame {}", v)),
+            },
+            (Some("snap"), Some(v)) if !v.is_empty() => snap = Some(v.to_string()),
+            (Some("every"), Some(v)) => match v.parse() {
+                Ok(n) if n > 0 => every = n,
+                _ => return Err(format!("bad tms9918 every {}", v)),
+            },
+            _ => return Err(format!("unknown tms9918 option {}", o)),
+        }
+    }
+    if let Some(s) = snap {
+        vdp.snapshots(&s, every);
+    }
+    Ok(vdp)
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::tms9918::*;
+
+    fn set_up(regs: [u8; 8]) -> Vdp {
+        let mut vdp = Vdp::new();
+        for (r, &v) in regs.iter().enumerate() {
+            vdp.write(1, v);
+            vdp.write(1, 0x80 | r as u8);
+        }
+        vdp.set_frame(10);
+        vdp
+    }
+    fn poke(vdp: &mut Vdp, address: u16, data: &[u8]) {
+        vdp.write(1, address as u8);
+        vdp.write(1, 0x40 | (address >> 8) as u8);
+        for &v in data {
+            vdp.write(0, v);
+        }
+    }
+    fn frame(vdp: &mut Vdp) -> u8 {
+        vdp.tick(10);
+        vdp.read(1)
+    }
+    fn px(vdp: &Vdp, x: usize, y: usize) -> u8 {
+        vdp.picture[y * WIDTH + x]
+    }
+
+    #[test]
+    fn vram_through_the_ports() {
+        let mut vdp = Vdp::new();
+        poke(&mut vdp, 0x3FFF, &[1, 2, 3]);     // and around
+        vdp.write(1, 0xFF);
+        vdp.write(1, 0x3F);                     // read setup, the buffer fills
+        assert_eq!(vdp.read(0), 1);
+        assert_eq!(vdp.read(0), 2);
+        vdp.write(1, 0x12);
+        vdp.read(1);                            // resets the latch
+        vdp.write(1, 0x01);
+        vdp.write(1, 0x00);
+        assert_eq!(vdp.peek(0), 3);
+        assert_eq!(vdp.read(0), 3);
+        assert_eq!(vdp.read(0), 0);
+    }
+
+    #[test]
+    fn frame_interrupt() {
+        let mut vdp = set_up([0, 0x60, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(vdp.next_event(), Some(10));
+        vdp.tick(9);
+        assert!(!vdp.irq());
+        vdp.tick(1);
+        assert!(vdp.irq());
+        assert_eq!(vdp.next_event(), None);
+        assert_eq!(vdp.read(1) & F, F);
+        assert!(!vdp.irq());
+        vdp.write(1, 0x40);
+        vdp.write(1, 0x81);                     // interrupts off
+        vdp.tick(10);
+        assert!(!vdp.irq());
+    }
+
+    #[test]
+    fn graphics_1_and_blanking() {
+        // names at 0, colours at 2000, patterns at 800, backdrop 5
+        let mut vdp = set_up([0, 0x40, 0, 0x80, 0x01, 0x20, 0x03, 0x05]);
+        poke(&mut vdp, 0x1000, &[0xD0]);
+        poke(&mut vdp, 0x0001, &[1]);
+        poke(&mut vdp, 0x0808, &[0xF0]);
+        poke(&mut vdp, 0x2000, &[0x60]);
+        frame(&mut vdp);
+        assert_eq!((px(&vdp, 8, 0), px(&vdp, 12, 0), px(&vdp, 8, 1)), (6, 5, 5));
+        vdp.write(1, 0x00);
+        vdp.write(1, 0x81);
+        frame(&mut vdp);
+        assert_eq!(px(&vdp, 8, 0), 5);
+        assert_eq!(vdp.rgb()[..3], [0x7D, 0x76, 0xFC]);
+    }
+
+    #[test]
+    fn text_multicolour_and_graphics_2() {
+        let mut vdp = set_up([0, 0x50, 0, 0, 0x01, 0, 0, 0xF4]);
+        poke(&mut vdp, 0x0001, &[1]);
+        poke(&mut vdp, 0x0808, &[0xF0]);
+        frame(&mut vdp);                        // 6 pixels a character from x 8
+        assert_eq!((px(&vdp, 7, 0), px(&vdp, 14, 0), px(&vdp, 17, 0), px(&vdp, 18, 0)), (4, 15, 15, 4));
+
+        let mut vdp = set_up([0, 0x48, 0, 0, 0x01, 0x20, 0, 0x01]);
+        poke(&mut vdp, 0x1000, &[0xD0]);
+        poke(&mut vdp, 0x0020, &[1]);           // row 1, rows 4-7 of the pattern
+        poke(&mut vdp, 0x080A, &[0x9A, 0xBC]);
+        frame(&mut vdp);
+        assert_eq!((px(&vdp, 0, 8), px(&vdp, 4, 8), px(&vdp, 4, 12)), (9, 10, 12));
+
+        // names at 3800, patterns at 0, colours at 2000, no masking
+        let mut vdp = set_up([0x02, 0x40, 0x0E, 0xFF, 0x03, 0x20, 0, 0x01]);
+        poke(&mut vdp, 0x1000, &[0xD0]);
+        poke(&mut vdp, 0x0800, &[0xFF]);
+        poke(&mut vdp, 0x2800, &[0x70]);
+        frame(&mut vdp);
+        assert_eq!((px(&vdp, 0, 0), px(&vdp, 0, 64), px(&vdp, 0, 128)), (1, 7, 1));
+    }
+
+    #[test]
+    fn fifth_sprite_and_coincidence() {
+        let mut vdp = set_up([0, 0x40, 0, 0, 0, 0x20, 0x03, 0x01]);
+        poke(&mut vdp, 0x1800, &[0xFF; 8]);
+        for n in 0..5 {
+            poke(&mut vdp, 0x1000 + n * 4, &[9, 16 * n as u8, 0, 2 + n as u8]);
+        }
+        poke(&mut vdp, 0x1014, &[0xD0]);
+        assert_eq!(frame(&mut vdp), F | FIFTH | 4);
+        assert_eq!((px(&vdp, 0, 10), px(&vdp, 48, 17), px(&vdp, 64, 10), px(&vdp, 0, 9)), (2, 5, 1, 1));
+        poke(&mut vdp, 0x1005, &[4]);           // sprite 1 over sprite 0
+        poke(&mut vdp, 0x1010, &[0xD0]);
+        assert_eq!(frame(&mut vdp), F | C | 4);
+        assert_eq!((px(&vdp, 4, 10), px(&vdp, 8, 10)), (2, 3));
+        assert_eq!(vdp.read(1), 4);             // flags cleared, the number stays
+    }
+
+    #[test]
+    fn big_magnified_and_early_sprites() {
+        let mut vdp = set_up([0, 0x41, 0, 0, 0, 0x20, 0x03, 0x01]);
+        poke(&mut vdp, 0x1800, &[0xFF; 8]);
+        poke(&mut vdp, 0x1000, &[0xFF, 40, 0, 0x87, 0xD0]);    // from the top line, 32 to the left
+        assert_eq!(frame(&mut vdp), F | 1);
+        assert_eq!((px(&vdp, 8, 0), px(&vdp, 23, 15), px(&vdp, 7, 0), px(&vdp, 24, 0), px(&vdp, 8, 16)), (7, 7, 1, 1, 1));
+
+        let mut vdp = set_up([0, 0x42, 0, 0, 0, 0x20, 0x03, 0x01]);
+        poke(&mut vdp, 0x1830, &[0xFF; 8]);     // pattern 6, top right
+        poke(&mut vdp, 0x1000, &[0xFF, 0, 5, 0x0A, 0xD0]);     // 5 is 4 for 16x16
+        frame(&mut vdp);
+        assert_eq!((px(&vdp, 7, 0), px(&vdp, 8, 0), px(&vdp, 15, 7), px(&vdp, 8, 8)), (1, 10, 10, 1));
+    }
+}
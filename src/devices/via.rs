@@ -0,0 +1,766 @@
+/*
+ * MOS 6522 VIA, Versatile Interface Adapter: two 8 bit ports with their handshake lines, two
+ * timers, a shift register and the interrupt logic. 16 registers, mirrored over the window.
+ *
+ * The VIA is clocked by phi2, so everything here counts cpu cycles. Pins are levels: whoever
+ * sits on the other side (a test, another device) drives the inputs with the set_ methods and
+ * reads the outputs back, then calls Bus::update so the interrupt line follows. Inputs float high.
+ *
+ * Timing is the usual emulator compromise: timer 1 interrupts N + 1 cycles after loading N (the
+ * chip says N + 1.5) and free runs with a period of N + 2. Shifting under T2 uses the T2 low latch,
+ * a bit every 2 * (N + 2) cycles, under phi2 a bit every 2 cycles. Shift clock out on CB1, falling
+ * edge puts a bit out on CB2, rising edge shifts.
+ *
+ * Wired is a VIA with buses on its ports, for bit banging drivers: SPI on port B (PB0 SCK, PB1
+ * MOSI, PB2 select of slave 0, PB7 MISO) and I2C on port A (PA0 SCL, PA7 SDA). I2C lines are open
+ * drain: a pin is pulled low by making it an output with a 0 in the data register, released by
+ * making it an input. An output at 1 releases it as well. Pins nobody drives float high.
+ * An HD44780 LCD goes where Ben Eater puts it: data on port B, PA5 RS, PA6 R/W, PA7 E, or with the
+ * 4 bit interface all on port B, PB0-PB3 D4-D7, PB4 RS, PB5 R/W, PB6 E.
+ */
+
+use devices::Device;
+use devices::spi::Spi;
+use devices::i2c::I2c;
+use devices::{sdcard, eeprom, rtc, lcd};
+use devices::lcd::Lcd;
+
+// registers
+const ORB: u16 = 0x0;
+const ORA: u16 = 0x1;
+const DDRB: u16 = 0x2;
+const DDRA: u16 = 0x3;
+const T1CL: u16 = 0x4;
+const T1CH: u16 = 0x5;
+const T1LL: u16 = 0x6;
+const T1LH: u16 = 0x7;
+const T2CL: u16 = 0x8;
+const T2CH: u16 = 0x9;
+const SR: u16 = 0xA;
+const ACR: u16 = 0xB;
+const PCR: u16 = 0xC;
+const IFR: u16 = 0xD;
+const IER: u16 = 0xE;
+const ORA_NH: u16 = 0xF;    // port A, no handshake
+
+// interrupt flags
+const CA2: u8 = 0x01;
+const CA1: u8 = 0x02;
+const SHIFT: u8 = 0x04;
+const CB2: u8 = 0x08;
+const CB1: u8 = 0x10;
+const T2: u8 = 0x20;
+const T1: u8 = 0x40;
+
+pub struct Via {
+    ora: u8,
+    orb: u8,
+    ddra: u8,
+    ddrb: u8,
+    pa_in: u8,          // levels driven from outside
+    pb_in: u8,
+    ira: u8,            // inputs latched on CA1/CB1
+    irb: u8,
+    t1c: u16,
+    t1l: u16,
+    t1_reload: bool,    // free run: counter was FFFF, latch goes in next cycle
+    t1_armed: bool,     // one shot: interrupt on next underflow
+    pb7: bool,          // timer 1 output
+    t2c: u16,
+    t2l: u8,            // only the low byte is latched, high goes straight into the counter
+    t2_armed: bool,
+    sr: u8,
+    sr_bits: u8,        // shifted since the last SR access, 8 is done
+    sr_timer: u64,      // cycles to the next shift clock edge
+    sr_clock: bool,     // internal shift clock level on CB1
+    sr_out: bool,       // data bit on CB2
+    acr: u8,
+    pcr: u8,
+    ifr: u8,
+    ier: u8,
+    ca1_in: bool,
+    ca2_in: bool,
+    cb1_in: bool,
+    cb2_in: bool,
+    ca2_out: bool,
+    cb2_out: bool,
+    ca2_pulse: bool,    // pulse output, back high next cycle
+    cb2_pulse: bool,
+}
+
+impl Via {
+    pub fn new() -> Via {
+        Via {
+            ora: 0, orb: 0, ddra: 0, ddrb: 0,
+            pa_in: 0xFF, pb_in: 0xFF, ira: 0, irb: 0,
+            t1c: 0, t1l: 0, t1_reload: false, t1_armed: false, pb7: true,
+            t2c: 0, t2l: 0, t2_armed: false,
+            sr: 0, sr_bits: 8, sr_timer: 0, sr_clock: true, sr_out: true,
+            acr: 0, pcr: 0, ifr: 0, ier: 0,
+            ca1_in: true, ca2_in: true, cb1_in: true, cb2_in: true,
+            ca2_out: true, cb2_out: true, ca2_pulse: false, cb2_pulse: false,
+        }
+    }
+
+    // pin levels, outputs where the DDR says so
+    pub fn port_a(&self) -> u8 {
+        self.ora & self.ddra | self.pa_in & !self.ddra
+    }
+    pub fn port_b(&self) -> u8 {
+        let v = self.orb & self.ddrb | self.pb_in & !self.ddrb;
+        if self.acr & 0x80 != 0 { v & 0x7F | (self.pb7 as u8) << 7 } else { v }
+    }
+
+    pub fn set_port_a(&mut self, v: u8) {
+        self.pa_in = v;
+    }
+    pub fn set_port_b(&mut self, v: u8) {
+        let pb6_falls = self.pb_in & 0x40 != 0 && v & 0x40 == 0;
+        self.pb_in = v;
+        if pb6_falls && self.acr & 0x20 != 0 {
+            // timer 2 counting pulses
+            self.t2c = self.t2c.wrapping_sub(1);
+            if self.t2c == 0 && self.t2_armed {
+                self.ifr |= T2;
+                self.t2_armed = false;
+            }
+        }
+    }
+
+    fn ca2_mode(&self) -> u8 {
+        (self.pcr >> 1) & 7
+    }
+    fn cb2_mode(&self) -> u8 {
+        (self.pcr >> 5) & 7
+    }
+    fn sr_mode(&self) -> u8 {
+        (self.acr >> 2) & 7
+    }
+    fn sr_internal(&self) -> bool {
+        matches!(self.sr_mode(), 1 | 2 | 4 | 5 | 6)
+    }
+    // shifting under the internal clock, mode 4 never stops
+    fn sr_running(&self) -> bool {
+        self.sr_internal() && (self.sr_bits < 8 || self.sr_mode() == 4)
+    }
+    // half a period of the internal shift clock
+    fn sr_half(&self) -> u64 {
+        match self.sr_mode() {
+            2 | 6 => 1,
+            _ => self.t2l as u64 + 2,
+        }
+    }
+    // SR accessed: clear the flag and start over
+    fn sr_start(&mut self) {
+        self.ifr &= !SHIFT;
+        self.sr_bits = 0;
+        self.sr_clock = true;
+        self.sr_timer = self.sr_half();
+    }
+    fn shift_edge(&mut self, rising: bool) {
+        let m = self.sr_mode();
+        if !rising {
+            if m >= 4 {
+                self.sr_out = self.sr & 0x80 != 0;
+            }
+            return;
+        }
+        if m >= 4 {
+            self.sr = self.sr.rotate_left(1);
+        } else {
+            self.sr = self.sr << 1 | self.cb2_in as u8;
+        }
+        self.sr_bits += 1;
+        if m == 4 {
+            self.sr_bits %= 8;          // free running, no interrupt
+        } else if self.sr_bits == 8 {
+            self.ifr |= SHIFT;
+        }
+    }
+
+    // ORA accessed: clear the flags, do the handshake
+    fn port_a_access(&mut self) {
+        let m = self.ca2_mode();
+        self.ifr &= !CA1;
+        if !(m < 4 && m & 1 != 0) {
+            self.ifr &= !CA2;           // unless independent
+        }
+        if m == 4 || m == 5 {
+            self.ca2_out = false;
+            self.ca2_pulse = m == 5;
+        }
+    }
+    // ORB accessed. The handshake is on writes only
+    fn port_b_access(&mut self, write: bool) {
+        let m = self.cb2_mode();
+        self.ifr &= !CB1;
+        if !(m < 4 && m & 1 != 0) {
+            self.ifr &= !CB2;
+        }
+        if write && (m == 4 || m == 5) {
+            self.cb2_out = false;
+            self.cb2_pulse = m == 5;
+        }
+    }
+
+    // register contents, no side effects
+    fn register(&self, reg: u16) -> u8 {
+        match reg & 0x0F {
+            ORB => {
+                let pins = if self.acr & 0x02 != 0 { self.irb } else { self.port_b() };
+                let v = self.orb & self.ddrb | pins & !self.ddrb;
+                if self.acr & 0x80 != 0 { v & 0x7F | (self.pb7 as u8) << 7 } else { v }
+            }
+            ORA | ORA_NH => if self.acr & 0x01 != 0 { self.ira } else { self.port_a() },
+            DDRB => self.ddrb,
+            DDRA => self.ddra,
+            T1CL => self.t1c as u8,
+            T1CH => (self.t1c >> 8) as u8,
+            T1LL => self.t1l as u8,
+            T1LH => (self.t1l >> 8) as u8,
+            T2CL => self.t2c as u8,
+            T2CH => (self.t2c >> 8) as u8,
+            SR => self.sr,
+            ACR => self.acr,
+            PCR => self.pcr,
+            IFR => self.ifr | if self.irq() { 0x80 } else { 0 },
+            IER => self.ier | 0x80,
+            _ => unreachable!(),
+        }
+    }
+
+    // a cycle
+    fn step(&mut self) {
+        if self.ca2_pulse {
+            self.ca2_pulse = false;
+            self.ca2_out = true;
+        }
+        if self.cb2_pulse {
+            self.cb2_pulse = false;
+            self.cb2_out = true;
+        }
+        if self.t1_reload {
+            self.t1_reload = false;
+            self.t1c = self.t1l;
+        } else {
+            if self.t1c == 0 {
+                if self.acr & 0x40 != 0 {
+                    self.ifr |= T1;
+                    self.pb7 = !self.pb7;
+                    self.t1_reload = true;
+                } else if self.t1_armed {
+                    self.ifr |= T1;
+                    self.pb7 = true;
+                    self.t1_armed = false;
+                }
+            }
+            self.t1c = self.t1c.wrapping_sub(1);
+        }
+        if self.acr & 0x20 == 0 {
+            if self.t2c == 0 && self.t2_armed {
+                self.ifr |= T2;
+                self.t2_armed = false;
+            }
+            self.t2c = self.t2c.wrapping_sub(1);
+        }
+        if self.sr_running() {
+            self.sr_timer -= 1;
+            if self.sr_timer == 0 {
+                self.sr_timer = self.sr_half();
+                self.sr_clock = !self.sr_clock;
+                let rising = self.sr_clock;
+                self.shift_edge(rising);
+            }
+        }
+    }
+
+    // cycles that can go by with counters counting and nothing else happening
+    fn quiet(&self) -> u64 {
+        if self.ca2_pulse || self.cb2_pulse || self.t1_reload {
+            return 0;
+        }
+        let mut q = self.t1c as u64;
+        if self.acr & 0x20 == 0 {
+            q = q.min(self.t2c as u64);
+        }
+        if self.sr_running() {
+            q = q.min(self.sr_timer - 1);
+        }
+        q
+    }
+}
+
+impl Device for Via {
+    fn read(&mut self, reg: u16) -> u8 {
+        let v = self.register(reg);
+        match reg & 0x0F {
+            ORB => self.port_b_access(false),
+            ORA => self.port_a_access(),
+            T1CL => self.ifr &= !T1,
+            T2CL => self.ifr &= !T2,
+            SR => self.sr_start(),
+            _ => {}
+        }
+        v
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        match reg & 0x0F {
+            ORB => {
+                self.orb = v;
+                self.port_b_access(true);
+            }
+            ORA => {
+                self.ora = v;
+                self.port_a_access();
+            }
+            ORA_NH => self.ora = v,
+            DDRB => self.ddrb = v,
+            DDRA => self.ddra = v,
+            T1CL | T1LL => self.t1l = self.t1l & 0xFF00 | v as u16,
+            T1CH => {
+                self.t1l = self.t1l & 0x00FF | (v as u16) << 8;
+                self.t1c = self.t1l;
+                self.t1_reload = false;
+                self.t1_armed = true;
+                self.ifr &= !T1;
+                self.pb7 = false;
+            }
+            T1LH => {
+                self.t1l = self.t1l & 0x00FF | (v as u16) << 8;
+                self.ifr &= !T1;
+            }
+            T2CL => self.t2l = v,
+            T2CH => {
+                self.t2c = self.t2l as u16 | (v as u16) << 8;
+                self.t2_armed = true;
+                self.ifr &= !T2;
+            }
+            SR => {
+                self.sr = v;
+                self.sr_start();
+            }
+            ACR => {
+                let running = self.sr_running();
+                self.acr = v;
+                if self.sr_running() && !running {
+                    self.sr_timer = self.sr_half();     // free running shifts start with no SR access
+                }
+            }
+            PCR => self.pcr = v,
+            IFR => self.ifr &= !v,
+            IER => {
+                if v & 0x80 != 0 {
+                    self.ier |= v & 0x7F;
+                } else {
+                    self.ier &= !v;
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.register(reg)
+    }
+    fn tick(&mut self, mut cycles: u64) {
+        while cycles > 0 {
+            let q = self.quiet().min(cycles);
+            if q > 0 {
+                self.t1c -= q as u16;
+                if self.acr & 0x20 == 0 {
+                    self.t2c -= q as u16;
+                }
+                if self.sr_running() {
+                    self.sr_timer -= q;
+                }
+                cycles -= q;
+            } else {
+                self.step();
+                cycles -= 1;
+            }
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        let mut n = !0u64;
+        if self.ca2_pulse || self.cb2_pulse || self.t1_reload {
+            n = 1;
+        }
+        if self.t1_armed || self.acr & 0x40 != 0 {
+            n = n.min(self.t1c as u64 + 1);
+        }
+        if self.t2_armed && self.acr & 0x20 == 0 {
+            n = n.min(self.t2c as u64 + 1);
+        }
+        if self.sr_running() {
+            n = n.min(self.sr_timer);
+        }
+        if n == !0 { None } else { Some(n) }
+    }
+    fn irq(&self) -> bool {
+        self.ifr & self.ier & 0x7F != 0
+    }
+}
+
+// the handshake lines, for whoever is on the other side: the tests, or a machine wiring them up
+#[cfg_attr(not(test), allow(dead_code))]
+impl Via {
+    pub fn ca2(&self) -> bool {
+        match self.ca2_mode() {
+            0..=3 => self.ca2_in,
+            6 => false,
+            7 => true,
+            _ => self.ca2_out,
+        }
+    }
+    pub fn cb1(&self) -> bool {
+        if self.sr_internal() { self.sr_clock } else { self.cb1_in }
+    }
+    pub fn cb2(&self) -> bool {
+        if self.sr_mode() >= 4 {
+            return self.sr_out;
+        }
+        match self.cb2_mode() {
+            0..=3 => self.cb2_in,
+            6 => false,
+            7 => true,
+            _ => self.cb2_out,
+        }
+    }
+
+    pub fn set_ca1(&mut self, level: bool) {
+        if level != self.ca1_in && level == (self.pcr & 0x01 != 0) {
+            self.ifr |= CA1;
+            self.ira = self.port_a();
+            if self.ca2_mode() == 4 {
+                self.ca2_out = true;        // handshake: data taken
+            }
+        }
+        self.ca1_in = level;
+    }
+    pub fn set_ca2(&mut self, level: bool) {
+        let m = self.ca2_mode();
+        if m < 4 && level != self.ca2_in && level == (m & 2 != 0) {
+            self.ifr |= CA2;
+        }
+        self.ca2_in = level;
+    }
+    pub fn set_cb1(&mut self, level: bool) {
+        if level != self.cb1_in {
+            if level == (self.pcr & 0x10 != 0) {
+                self.ifr |= CB1;
+                self.irb = self.port_b();
+                if self.cb2_mode() == 4 {
+                    self.cb2_out = true;
+                }
+            }
+            let m = self.sr_mode();
+            if (m == 3 || m == 7) && self.sr_bits < 8 {
+                self.shift_edge(level);     // external shift clock
+            }
+        }
+        self.cb1_in = level;
+    }
+    pub fn set_cb2(&mut self, level: bool) {
+        let m = self.cb2_mode();
+        if m < 4 && level != self.cb2_in && level == (m & 2 != 0) {
+            self.ifr |= CB2;
+        }
+        self.cb2_in = level;
+    }
+}
+
+pub struct Wired {
+    via: Via,
+    spi: Option<Spi>,
+    i2c: Option<I2c>,
+    lcd: Option<(Lcd, bool)>,       // true on the 4 bit wiring
+}
+
+impl Wired {
+    pub fn new(spi: Option<Spi>, i2c: Option<I2c>, lcd: Option<(Lcd, bool)>) -> Wired {
+        let mut w = Wired { via: Via::new(), spi, i2c, lcd };
+        w.wire();
+        w
+    }
+
+    // what the LCD shows, for the host side
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub fn lcd(&self) -> Option<&Lcd> {
+        self.lcd.as_ref().map(|l| &l.0)
+    }
+
+    // follow the port pins after anything that can move them
+    fn wire(&mut self) {
+        if let Some(ref mut spi) = self.spi {
+            let pb = self.via.port_b();
+            spi.set_select(0xFE | (pb >> 2) & 1);
+            let miso = spi.clock(pb & 0x01 != 0, pb & 0x02 != 0);
+            self.via.set_port_b(0x7F | (miso as u8) << 7);
+        }
+        if let Some(ref mut i2c) = self.i2c {
+            self.via.set_port_a(0xFF);
+            let pa = self.via.port_a();     // what we drive, the rest released
+            let sda = i2c.pins(pa & 0x01 != 0, pa & 0x80 != 0);
+            self.via.set_port_a(0x7E | pa & 0x01 | (sda as u8) << 7);
+        }
+        if let Some((ref mut lcd, four)) = self.lcd {
+            self.via.set_port_b(0xFF);
+            let pb = self.via.port_b();
+            if four {
+                let d = lcd.pins(pb & 0x10 != 0, pb & 0x20 != 0, pb & 0x40 != 0, pb << 4);
+                self.via.set_port_b(0xF0 | d >> 4);
+            } else {
+                let pa = self.via.port_a();
+                let d = lcd.pins(pa & 0x20 != 0, pa & 0x40 != 0, pa & 0x80 != 0, pb);
+                self.via.set_port_b(d);
+            }
+        }
+    }
+}
+
+impl Device for Wired {
+    fn read(&mut self, reg: u16) -> u8 {
+        self.via.read(reg)
+    }
+    fn write(&mut self, reg: u16, v: u8) {
+        self.via.write(reg, v);
+        self.wire();
+    }
+    fn peek(&mut self, reg: u16) -> u8 {
+        self.via.peek(reg)
+    }
+    fn tick(&mut self, cycles: u64) {
+        self.via.tick(cycles);
+        if let Some(ref mut i2c) = self.i2c {
+            i2c.tick(cycles);
+        }
+        if let Some((ref mut lcd, _)) = self.lcd {
+            lcd.tick(cycles);
+        }
+    }
+    fn next_event(&self) -> Option<u64> {
+        let lcd = self.lcd.as_ref().and_then(|l| l.0.next_event());
+        match (self.via.next_event(), lcd) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+    fn irq(&self) -> bool {
+        self.via.irq()
+    }
+}
+
+// for the command line: sd=image (ro for write protected) on SPI, eeprom=file and rtc=host|seconds
+// (since 1970, fixed for reproducible runs) on I2C, lcd or lcd4 with size=colsxrows and show
+pub fn create(opts: &[&str]) -> Result<Wired, String> {
+    let mut spi = None;
+    let mut i2c = None;
+    let mut sd = None;
+    let mut readonly = false;
+    let mut lcd_opts = Vec::new();
+    let mut four = None;
+    for o in opts {
+        let mut kv = o.splitn(2, '=');
+        match (kv.next(), kv.next()) {
+            (Some("lcd"), None) => four = Some(false),
+            (Some("lcd4"), None) => four = Some(true),
+            (Some("size"), Some(_)) | (Some("show"), None) => lcd_opts.push(*o),
+            (Some("sd"), Some(f)) => sd = Some(f),
+            (Some("ro"), None) => readonly = true,
+            (Some("eeprom"), Some(f)) => {
+                let e = eeprom::Eeprom::open(f, 0)?;
+                i2c.get_or_insert_with(I2c::new).attach(Box::new(e));
+            }
+            (Some("rtc"), Some(t)) => {
+                let r = match t {
+                    "host" => rtc::Rtc::host(),
+                    t => rtc::Rtc::new(t.parse::<u64>().map_err(|_| format!("bad rtc time {}", t))?),
+                };
+                i2c.get_or_insert_with(I2c::new).attach(Box::new(r));
+            }
+            _ => return Err(format!("unknown via option {}", o)),
+        }
+    }
+    if let Some(f) = sd {
+        let mut s = Spi::new();
+        s.attach(0, Box::new(sdcard::SdCard::open(f, readonly)?));
+        spi = Some(s);
+    }
+    let lcd = match four {
+        Some(_) if spi.is_some() => return Err("the LCD and SPI both want port B".to_string()),
+        Some(false) if i2c.is_some() => return Err("the LCD and I2C both want PA7".to_string()),
+        Some(four) => Some((lcd::create(&lcd_opts)?, four)),
+        None if !lcd_opts.is_empty() => return Err(format!("{} without lcd", lcd_opts.join(":"))),
+        None => None,
+    };
+    Ok(Wired::new(spi, i2c, lcd))
+}
+
+#[cfg(test)]
+mod tests {
+    use devices::Device;
+    use devices::via::*;
+
+    #[test]
+    fn ca1_latches_port_a_and_ends_the_handshake() {
+        let mut via = Via::new();
+        via.write(ACR, 0x01);           // latch port A
+        via.write(PCR, 0x08);           // CA2 handshake output, CA1 active falling
+        via.write(ORA, 0x00);
+        assert!(!via.ca2());            // data ready
+        via.set_port_a(0x5A);
+        via.set_ca1(false);
+        assert!(via.ca2());             // taken
+        assert_eq!(via.read(IFR) & CA1, CA1);
+        via.set_port_a(0x00);
+        assert_eq!(via.read(ORA), 0x5A);
+        assert_eq!(via.read(IFR) & CA1, 0);
+    }
+
+    #[test]
+    fn ca2_and_cb2_inputs_flag_their_edge() {
+        let mut via = Via::new();
+        via.write(PCR, 0x04);           // CA2 rising edge, CB2 falling edge
+        via.set_ca2(false);
+        via.set_cb2(false);
+        assert_eq!(via.read(IFR), CB2);
+        via.set_ca2(true);
+        assert_eq!(via.read(IFR), CB2 | CA2);
+        assert!(via.ca2() && !via.cb2());
+    }
+
+    #[test]
+    fn cb2_pulses_for_a_cycle_on_orb_writes() {
+        let mut via = Via::new();
+        via.write(PCR, 0xA0);
+        assert!(via.cb2());
+        via.write(ORB, 0x12);
+        assert!(!via.cb2());
+        assert_eq!(via.next_event(), Some(1));
+        via.tick(1);
+        assert!(via.cb2());
+    }
+
+    #[test]
+    fn shifts_in_under_cb1() {
+        let mut via = Via::new();
+        via.write(ACR, 0x0C);
+        via.read(SR);
+        for i in (0..8).rev() {
+            via.set_cb2(0xA5 >> i & 1 != 0);
+            via.set_cb1(false);
+            assert!(!via.cb1());
+            via.set_cb1(true);
+        }
+        assert_eq!(via.read(IFR) & SHIFT, SHIFT);
+        assert_eq!(via.read(SR), 0xA5);
+    }
+
+    #[test]
+    fn lcd_on_the_ports() {
+        let mut w = create(&["lcd"]).unwrap();
+        w.write(DDRA, 0xE0);
+        w.write(DDRB, 0xFF);
+        w.tick(50);         // E floated high till now, going low it latched FF: DDRAM address 7F
+        for &(rs, v) in [(0, 0x38), (0, 0x0C), (0, 0x80), (1, b'H'), (1, b'i')].iter() {
+            w.write(ORB, v);
+            w.write(ORA, rs << 5 | 0x80);   // E high, then low takes it
+            w.write(ORA, rs << 5);
+            w.tick(50);
+        }
+        let text = w.lcd().unwrap().text();
+        assert_eq!(text[0].trim_end(), "Hi");
+    }
+
+    #[test]
+    fn timer_1_one_shot() {
+        let mut via = Via::new();
+        via.write(IER, 0x80 | T1);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        assert_eq!(via.next_event(), Some(11));
+        via.tick(10);
+        assert!(!via.irq());
+        via.tick(1);
+        assert!(via.irq());
+        via.read(T1CL);
+        assert!(!via.irq());
+        via.tick(100_000);
+        assert_eq!(via.read(IFR), 0);   // once only
+    }
+
+    #[test]
+    fn timer_1_free_runs_and_toggles_pb7() {
+        let mut via = Via::new();
+        via.write(ACR, 0xC0);
+        via.write(T1CL, 10);
+        via.write(T1CH, 0);
+        assert_eq!(via.port_b() & 0x80, 0);
+        via.tick(11);
+        assert_eq!(via.read(IFR), T1);
+        via.write(IFR, T1);
+        assert_eq!(via.port_b() & 0x80, 0x80);
+        via.tick(11);
+        assert_eq!(via.read(IFR), 0);
+        via.tick(1);                    // a period of N + 2
+        assert_eq!(via.read(IFR), T1);
+        assert_eq!(via.port_b() & 0x80, 0);
+    }
+
+    #[test]
+    fn timer_2_one_shot_and_pulse_counting() {
+        let mut via = Via::new();
+        via.write(T2CL, 5);
+        via.write(T2CH, 0);
+        via.tick(5);
+        assert_eq!(via.read(IFR), 0);
+        via.tick(1);
+        assert_eq!(via.read(IFR), T2);
+        via.read(T2CL);
+        assert_eq!(via.read(IFR), 0);
+
+        via.write(ACR, 0x20);
+        via.write(T2CL, 3);
+        via.write(T2CH, 0);
+        via.tick(1000);
+        for _ in 0..3 {
+            assert_eq!(via.read(IFR), 0);
+            via.set_port_b(0xBF);
+            via.set_port_b(0xFF);
+        }
+        assert_eq!(via.read(IFR), T2);
+    }
+
+    #[test]
+    fn shifts_out_under_phi2() {
+        let mut via = Via::new();
+        via.write(ACR, 0x18);
+        via.write(SR, 0xA5);
+        let mut out = 0u8;
+        for _ in 0..8 {
+            via.tick(1);
+            assert!(!via.cb1());        // falling edge, the bit goes out
+            out = out << 1 | via.cb2() as u8;
+            via.tick(1);
+            assert!(via.cb1());
+        }
+        assert_eq!(out, 0xA5);
+        assert_eq!(via.read(IFR), SHIFT);
+        assert_eq!(via.next_event(), None);
+    }
+
+    #[test]
+    fn free_running_shift_starts_with_no_sr_access() {
+        let mut via = Via::new();
+        via.write(ACR, 0x10);
+        via.tick(10);
+        via.write(SR, 0x81);
+        via.write(ACR, 0x10);           // under T2, latch 0: a bit every 4 cycles
+        assert_eq!(via.next_event(), Some(2));
+        via.tick(4);
+        assert_eq!(via.read(SR), 0x03);
+        via.write(ACR, 0x10);
+        via.tick(28);
+        assert_eq!(via.peek(SR), 0x81);
+        assert_eq!(via.read(IFR), 0);   // and never interrupts
+    }
+}
@@ -1,43 +1,96 @@
 extern crate termion;
 
+// what the compiler and clippy say about the original cpu and disassembler code, left as it is
+#[allow(type_alias_bounds, unreachable_patterns)]
+#[allow(clippy::assertions_on_constants, clippy::assign_op_pattern, clippy::nonminimal_bool, clippy::unnecessary_cast)]
 mod cpu;
+#[allow(clippy::redundant_static_lifetimes)]
 mod disasm;
+mod bus;
+mod rng;
+mod devices;
 
-use std::ascii::AsciiExt;
 use std::fmt::Write as whatever;
 use std::io::{Read, stdout, Write};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
 use termion::raw::IntoRawMode;
 use termion::async_stdin;
-use cpu::Memory;
+use cpu::{Memory, BusFault};
 use cpu::P65;
+use bus::{Bus, Check, Fill};
+use rng::Rng;
+use devices::Keys;
+use devices::irqgen::IrqGenerator;
 
+const BATCH: u64 = 1000;    // most cycles run between looks at the keyboard
 
-const EXPLAIN: &'static str = "[options] hex-address:file [addr:file ..]\r\n\
+const EXPLAIN: &str = "[options] hex-address:file [addr:file ..]\r\n\
                                 \r\n\
                                 Load one or more blobs at specified hexadecimal addresses, \r\n\
                                 then launch via RESET vector or by direct jump (-j)\r\n\
                                 The RESET vector will be populated with meaningful value only if a binary\r\n\
                                 has been loaded to the appropriate location, covering top of memory, or if -p poke has been used.\r\n\
-                                Memory size is 64KB of 8 bit RAM\r\n\
+                                Memory is 64KB of 8 bit RAM, unless mapped otherwise with -m. Blobs loaded with -r are read only, like a ROM.\r\n\
+                                Reading from an address nobody answers returns the last value seen on the data bus.\r\n\
                                 Press Ctrl+q to quit, Ctrl+e to dump current status.\r\n\
                                 \r\n\
                                 Options:\r\n\
                                 \t-h: help\r\n\
                                 \t-d: dump trace to stderr\r\n\
-                                \t-k address: of the optional keyboard (mapped to stdin)\r\n\
-                                \t-t address: of the optional screen/printer character (stdout\r\n\
+                                \t-k address: of the optional keyboard (mapped to stdin), same as -a address:keyboard:case=upper\r\n\
+                                \t-t address: of the optional screen/printer character (stdout), same as -a address:console\r\n\
                                 \t-i address: of the optional irq/nmi generator, useful for tests\r\n\
+                                \t-a address:device[:option..] attach a device at address, multiple usage allowed. Devices and options:\r\n\
+                                \t\tvia (6522): sd=image (an SD card bit banged on port B: PB0 SCK, PB1 MOSI, PB2 select, PB7 MISO), ro\r\n\
+                                \t\t\teeprom=file (24LC256), rtc=host|seconds since 1970 (DS1307), on I2C on port A: PA0 SCL, PA7 SDA\r\n\
+                                \t\t\tlcd (HD44780: PB data, PA5 RS, PA6 R/W, PA7 E) or lcd4 (PB0-3 D4-7, PB4 RS, PB5 R/W, PB6 E), size=16x2, show\r\n\
+                                \t\tacia (6551): in=file out=file (else the console), clock=hz (1000000), wdc (65C51 transmit bug)\r\n\
+                                \t\tcia (6526): clock=hz (1000000), mains=50|60 (50), old (6526 IRQ timing, not 6526A)\r\n\
+                                \t\triot (6532): 256 bytes, RAM in the lower half, I/O in the upper\r\n\
+                                \t\tpia (6821): apple1 (Apple-1 keyboard and display on the console)\r\n\
+                                \t\tkeyboard: data and status registers, keys from the console. case=keep|upper|lower|swap\r\n\
+                                \t\tconsole: data and status registers, output to the console. eol=raw|cr|lf, delay=cycles a character\r\n\
+                                \t\ttimer: periodic or one shot interrupts. period=cycles (starts it at power on), nmi\r\n\
+                                \t\tspi (65spi): SPI controller. sd=image (an SD card on select 0), ro\r\n\
+                                \t\tlcd (hd44780): on the bus, RS is A0. size=colsxrows (16x2), show (top right of the terminal)\r\n\
+                                \t\tscreen: character cells in memory, drawn in a box on the terminal. size=colsxrows (40x25), charset=ascii|c64|apple2,\r\n\
+                                \t\t\tat=column,row of the box (1,1), dump (print the screen as text at the end)\r\n\
+                                \t\tbitmap: pixels in memory, then registers, frames to image files. size=widthxheight (320x200), bpp=1|2|4|8 (1),\r\n\
+                                \t\t\tframe=cycles between vertical blanks (16667), snap=file.png|file.ppm (# numbers them), every=cycles\r\n\
+                                \t\ttms9918 (vdp): data at 0, control and status at 1, 16KB of VRAM, 256x192 frames to image files. frame=cycles (16667),\r\n\
+                                \t\t\tsnap=file.png|file.ppm (# numbers them), every=frames (1)\r\n\
+                                \t\tdisk: 512 byte sectors by DMA. file=image (required), ro, delay=cycles of seek time\r\n\
+                                \t\tdebug: for tests. Exit with a status, print hex, read the cycle count, fail an assertion (exits with 255)\r\n\
                                 \t-j address: jump start to address\r\n\
-                                \t-p address:value poke value, multiple usage allowed\r\n";
+                                \t-p address:value poke value, multiple usage allowed\r\n\
+                                \t-r address:file load file as ROM, multiple usage allowed\r\n\
+                                \t-m address:size map size bytes of RAM at address, multiple usage allowed\r\n\
+                                \t-s address:size:cycles add wait states to every access in the range, multiple usage allowed\r\n\
+                                \t-z seed: random power on registers and RAM, reproducible from the (decimal) seed\r\n\
+                                \t-f xx|stripes[:size]|random: fill RAM at power on with hex value xx, 00/FF stripes (size 40), garbage\r\n\
+                                \t-w report|stop: on writes into ROM report the first one, or stop\r\n\
+                                \t-u report|stop: on reads from never written memory report the first one, or stop\r\n\
+                                \t-b: accesses to unmapped addresses are bus errors and stop, instead of open bus\r\n";
 
 // Simple test program for my 6502 simulator.
 
 fn main() {
 
-    let mut mem = MemoryArrayMess::new(65536).unwrap(); // the full awesome power of 64KB at your fingertips
+    let mut mem = Bus::new();
     let mut dump = false;
-    let mut loaded = false; // must load at least some code
+    let mut loads: Vec<(u16, String, bool)> = Vec::new();  // address, file, rom. Done when RAM is mapped
+    let mut pokes: Vec<(u16, u8)> = Vec::new();
+    let mut ram: Vec<(u16, usize)> = Vec::new();
+    let mut slow: Vec<(u16, usize, u8)> = Vec::new();
+    let mut seed: Option<u64> = None;
+    let mut fill: Option<Fill> = None;
     let mut jump: Option<u16> = None;
+    let mut irq_generator: Option<u16> = None;
+    let mut keyboard: Option<u16> = None;
+    let mut printer: Option<u16> = None;
+    let mut attached: Vec<(u16, String)> = Vec::new();    // address, device and options
 
     // not exceptional argument parsing. TODO: refactor this mess
     let mut ai = std::env::args();
@@ -45,23 +98,101 @@ fn main() {
     while let Some(s) = ai.next() {
         match s.as_ref() {
             s if !s.starts_with("-") => {
-                let va: Result<(),String>  =    s.split(':')  // it is a Result<(),&str> 
-                            .zip(s.split(':').skip(1))
-                            .next()
-                            .ok_or("Missing address and file name".to_string())
-                            .and_then(|(s1,s2)| u16::from_str_radix(s1,16)
-                                                        .map(|a| (a,s2))
-                                                        .or(Err("Wrong loading address".to_string())))
-                            .and_then(|(s1,s2)| if s2.len() > 0 { Ok((s1,s2)) } else { Err("Missing file name".to_string()) })
-                            .and_then(|(s1,s2)| load_binary(&mut mem, s2, s1).map_err(|e| e.to_string() ));
-                match va {
-                    Ok(_) => { loaded = true; },
+                match parse_load(s) {
+                    Ok((a, f)) => { loads.push((a, f.to_string(), false)); },
                     Err(ref e) =>  { println!("Error: {}", e); return },
                 }
             },
+            "-r" => {
+                match parse_load(&ai.next().unwrap_or("".to_string())) {
+                    Ok((a, f)) => { loads.push((a, f.to_string(), true)); },
+                    Err(ref e) =>  { println!("Error: {}", e); return },
+                }
+            },
+            "-m" => {
+                let arg = ai.next().unwrap_or("".to_string());
+                let mut p = arg.split(':');
+                let a2 = p.next().and_then(|v| u16::from_str_radix(v, 16).ok());
+                let s2 = p.next().and_then(|v| usize::from_str_radix(v, 16).ok());
+                match (a2, s2) {
+                    (Some(a), Some(size)) if size > 0 && size <= 0x10000 => { ram.push((a, size)); },
+                    _ => {
+                        println!("-m needs an hex address:size pair, size at most 10000");
+                        return;
+                    }
+                }
+            },
+            "-s" => {
+                let arg = ai.next().unwrap_or("".to_string());
+                let mut p = arg.split(':');
+                let a2 = p.next().and_then(|v| u16::from_str_radix(v, 16).ok());
+                let s2 = p.next().and_then(|v| usize::from_str_radix(v, 16).ok());
+                let c2 = p.next().and_then(|v| u8::from_str_radix(v, 16).ok());
+                match (a2, s2, c2) {
+                    (Some(a), Some(size), Some(c)) if size <= 0x10000 => { slow.push((a, size, c)); },
+                    _ => {
+                        println!("-s needs an hex address:size:cycles triple, size at most 10000");
+                        return;
+                    }
+                }
+            },
+            "-a" => {
+                let arg = ai.next().unwrap_or("".to_string());
+                let mut p = arg.splitn(2, ':');
+                match (p.next().and_then(|v| u16::from_str_radix(v, 16).ok()), p.next()) {
+                    (Some(a), Some(d)) if !d.is_empty() => { attached.push((a, d.to_string())); },
+                    _ => {
+                        println!("-a needs an hex address:device[:option..]");
+                        return;
+                    }
+                }
+            },
+            "-z" => {
+                match ai.next().and_then(|v| v.parse::<u64>().ok()) {
+                    Some(v) => { seed = Some(v); },
+                    None => {
+                        println!("-z needs a decimal seed");
+                        return;
+                    }
+                }
+            },
+            "-f" => {
+                let arg = ai.next().unwrap_or("".to_string());
+                let mut p = arg.split(':');
+                fill = match (p.next(), p.next()) {
+                    (Some("random"), None) => Some(Fill::Random(0)),    // seed is decided later
+                    (Some("stripes"), None) => Some(Fill::Stripes(0x40)),
+                    (Some("stripes"), Some(v)) => usize::from_str_radix(v, 16).ok()
+                                                        .and_then(|v| if v > 0 { Some(Fill::Stripes(v)) } else { None }),
+                    (Some(v), None) => u8::from_str_radix(v, 16).ok().map(Fill::Value),
+                    _ => None,
+                };
+                if fill.is_none() {
+                    println!("-f needs an hex value, stripes[:size] or random");
+                    return;
+                }
+            },
+            p @ "-w" | p @ "-u" => {
+                let check = match ai.next().as_ref().map(|s| s.as_ref()) {
+                    Some("report") => Check::Report,
+                    Some("stop") => Check::Stop,
+                    _ => {
+                        println!("{} needs report or stop", p);
+                        return;
+                    }
+                };
+                if p == "-w" {
+                    mem.rom_writes = check;
+                } else {
+                    mem.uninit_reads = check;
+                }
+            }
             "-d" => {
                 dump = true;
             }
+            "-b" => {
+                mem.unmapped_faults = true;
+            }
             "-p" => {
                 let arg = ai.next();
                 match arg {
@@ -81,7 +212,7 @@ fn main() {
                                                                      });
                         match (a2, v2) {
                             (Ok(a), Ok(v)) => {
-                                mem.write(a as usize, v);
+                                pokes.push((a, v));
                             }
                             (Err(a), _) | (_, Err(a)) => {
                                 println!("-p: {}", a);
@@ -97,11 +228,11 @@ fn main() {
                     println!("{} needs an argument.", p);
                     return;
                 }
-                let mut target = match p {
-                    "-k" => &mut mem.keyboard,
+                let target = match p {
+                    "-k" => &mut keyboard,
                     "-j" => &mut jump,
-                    "-t" => &mut mem.printer,
-                    "-i" => &mut mem.irq_generator,
+                    "-t" => &mut printer,
+                    "-i" => &mut irq_generator,
                     _ => {
                         panic!();
                     }
@@ -119,27 +250,103 @@ fn main() {
             }
         }
     }
-    if !loaded {
+    if loads.is_empty() {
         println!("Must specify at least one file to load");
         return;
     }
+    if ram.is_empty() {
+        ram.push((0x0000, 0x10000));    // the full awesome power of 64KB at your fingertips
+    }
+    for &(a, size) in ram.iter() {
+        if let Err(e) = mem.map_ram(a, size) {
+            println!("Error: {}", e);
+            return;
+        }
+    }
+    // -z randomises the registers too, -f random only RAM
+    let registers = seed;
+    if seed.is_some() && fill.is_none() {
+        fill = Some(Fill::Random(0));
+    }
+    if fill == Some(Fill::Random(0)) {
+        if seed.is_none() {
+            let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap();
+            seed = Some(now.as_secs() ^ now.subsec_nanos() as u64);
+            println!("Random fill seed: {}", seed.unwrap());
+        }
+        fill = Some(Fill::Random(seed.unwrap()));
+    }
+    if let Some(f) = fill {
+        mem.fill(f);
+    }
+    for &(a, size, cycles) in slow.iter() {
+        mem.set_wait_states(a, size, cycles);
+    }
+    // RAM first, then pokes, then devices, so blobs don't hit their registers, then ROMs, which
+    // are mapped over everything else
+    for &(a, ref f, _) in loads.iter().filter(|l| !l.2) {
+        if let Err(e) = load_binary(&mut mem, f, a) {
+            println!("Error: {}: {}", f, e);
+            return;
+        }
+    }
+    for &(a, v) in pokes.iter() {
+        mem.poke(a, v);
+    }
+    if let Some(a) = irq_generator {
+        mem.attach(a, 1, Box::new(IrqGenerator::new())).unwrap();
+    }
+    if let Some(a) = keyboard {
+        attached.insert(0, (a, "keyboard:case=upper".to_string()));
+    }
+    if let Some(a) = printer {
+        attached.insert(0, (a, "console".to_string()));
+    }
+    let keys: Keys = Rc::new(RefCell::new(VecDeque::new()));
+    for &(a, ref d) in attached.iter() {
+        let opts: Vec<&str> = d.split(':').collect();
+        let r = devices::create(opts[0], &opts[1..], &keys)
+            .and_then(|(dev, size)| mem.attach(a, size, dev).map_err(|e| e.to_string()));
+        if let Err(e) = r {
+            println!("Error: -a {:04x}:{}: {}", a, d, e);
+            return;
+        }
+    }
+    for &(a, ref f, _) in loads.iter().filter(|l| l.2) {
+        let rom = std::fs::read(f);
+        match rom.map_err(|e| e.to_string()).and_then(|r| mem.map_rom(a, r).map_err(|e| e.to_string())) {
+            Ok(_) => {},
+            Err(e) => { println!("Error: {}: {}", f, e); return },
+        }
+    }
 
 
-    let new_stdout = stdout();
-    let mut stdout = new_stdout.lock().into_raw_mode().unwrap();
-    let mut stdin = async_stdin().bytes();
+    // raw mode only with a terminal: CI runs have none, and end with the debug port
+    let raw = stdout().into_raw_mode().ok();
+    #[allow(clippy::unbuffered_bytes)]      // whatever was typed, a key at a time
+    let mut stdin = raw.as_ref().map(|_| async_stdin().bytes());
 
     let mut status_print = false;
-    let mut last_flush = 0u64;
+    let mut rom_violation: Option<(u16, u8, u16)> = None;   // address, value, pc
+    let mut uninit_violation: Option<(u16, bool, u16)> = None;  // address, fetch, pc
     let mut pr = P65::new();
-    pr.reset(&mut mem);
-    if jump.is_some() {
-        println!("jump: {}", jump.unwrap());
-        pr.jump(&mut mem, jump.unwrap());
+    mem.unchecked(|mem| pr.reset(mem));       // the vector may be left to -j, and it's not the program reading
+    if let Some(seed) = registers {
+        let mut rng = Rng::new(!seed);     // not the same garbage as RAM
+        let r: Vec<u8> = (0..5).map(|_| rng.next_u8()).collect();
+        pr.set_registers(r[0], r[1], r[2], r[3], r[4]);
     }
+    if let Some(j) = jump {
+        println!("jump: {}", j);
+        pr.jump(&mut mem, j);
+    }
+    // the bus clock starts with the program, the reset sequence doesn't tick it
+    mem.cycle = pr.cycle;
+    mem.update();
+    let mut exit_code = 0;
 
     loop {
-        match stdin.next() {
+        match stdin.as_mut().and_then(|s| s.next()) {
             Some(Ok(c)) => {
                 match c {
                     0x11 => {
@@ -151,9 +358,10 @@ fn main() {
                         status_print = true;
                     }
                     c => {
-                        if mem.keyboard.is_some() {
-                            let btmp = mem.keyboard.unwrap() as usize;
-                            mem.write(btmp as usize, c.to_ascii_uppercase());
+                        if Rc::strong_count(&keys) > 1 {
+                            // some device reads the console
+                            keys.borrow_mut().push_back(c);
+                            mem.update();
                         }
                     }
                 }
@@ -162,111 +370,99 @@ fn main() {
             None => {}
         }
 
-        pr.run(&mut mem, 1);             // one cycle at a time. best performance can be achieved in single/multiple op or cycle mode 
-        if mem.irq_generator.is_some() {
-            if mem.fire_irq {
-                pr.irq_set();
-            } else {
-                pr.irq_clear();
-                mem.fire_irq = false;
+        // run uninterrupted up to the next device event, and not too long so keys and output
+        // stay snappy. Devices are caught up lazily by the bus anyway, so this is only the upper bound.
+        // Tracing needs every cycle, and so does the PC of a violation to report
+        let watching = mem.rom_writes == Check::Report && rom_violation.is_none()
+                       || mem.uninit_reads == Check::Report && uninit_violation.is_none();
+        let batch = if dump || status_print || watching {
+            1
+        } else {
+            mem.next_event().map_or(BATCH, |n| n.clamp(1, BATCH))
+        };
+        let stop = pr.run(&mut mem, batch).err();
+        if let (None, Some((a, v))) = (rom_violation, mem.rom_violation) {
+            rom_violation = Some((a, v, pr.op_pc()));
+            if mem.rom_writes == Check::Report {
+                println!("\r\nWrite into ROM at {:04x}, value {:02x}, PC {:04x}\r", a, v, pr.op_pc());
             }
-            if mem.fire_nmi {
-                pr.nmi_set();
-            } else {
-                pr.nmi_clear();
-                mem.fire_nmi = false;
+        }
+        if let (None, Some((a, fetch))) = (uninit_violation, mem.uninit_violation) {
+            uninit_violation = Some((a, fetch, pr.op_pc()));
+            if mem.uninit_reads == Check::Report {
+                println!("\r\nUninitialized {} at {:04x}, PC {:04x}\r", if fetch { "opcode fetch" } else { "read" }, a, pr.op_pc());
             }
         }
-        if pr.cycle - last_flush >= 50_000 {
-            // flush output every 50K cycles. Gross!
-            stdout.flush().unwrap(); // we must flush to keep terminal operating
-            last_flush = pr.cycle;
+        if let Some(stop) = stop {
+            match stop.fault {
+                BusFault::Exit(code) => exit_code = code as i32,
+                BusFault::Assert(_) => {
+                    println!("\r\n{}\r", stop);
+                    exit_code = 255;
+                }
+                _ => println!("\r\n{}\r", stop),
+            }
+            break;
         }
         if status_print && pr.ts == 1 {
             println!("{}\r", status_string(&pr, &mut mem));
+            let _ = stdout().flush();
             status_print = false;
         }
         if dump {
             println!("{}\r", status_string(&pr, &mut mem));
+            let _ = stdout().flush();
         }
     }
-}
-
-fn load_binary<M: Memory>(mem: &mut M, name: &str, address: u16) -> std::io::Result<()> {
-    let f = try!(std::fs::File::open(name));
-    for (i, v) in f.bytes().enumerate() {
-        mem.write(address.wrapping_add(i as u16) as usize, v?);
+    if let Some((a, v, pc)) = rom_violation {
+        println!("\r\nFirst write into ROM at {:04x}, value {:02x}, PC {:04x}. {} writes into ROM in total\r",
+                 a, v, pc, mem.rom_violations);
     }
-    Ok(())
+    if let Some((a, fetch, pc)) = uninit_violation {
+        println!("\r\nFirst uninitialized {} at {:04x}, PC {:04x}. {} uninitialized reads in total\r",
+                 if fetch { "opcode fetch" } else { "read" }, a, pc, mem.uninit_violations);
+    }
+    // exit skips destructors: devices flush their output, the terminal gets out of raw mode
+    drop(mem);
+    drop(raw);
+    std::process::exit(exit_code);
 }
 
-
-// we need something better for memory. should manage roms, stratified layouts, etc.
-// TODO: devices should be attached/detached to some bus manager object.
-// Memory should be memory, an array and nothing more.
-struct MemoryArrayMess {
-    pub m: Vec<u8>,
-    pub fire_irq: bool,
-    pub fire_nmi: bool,
-    pub keyboard: Option<u16>,
-    pub printer: Option<u16>,
-    pub irq_generator: Option<u16>,
+// split an hex-address:file argument
+fn parse_load(s: &str) -> Result<(u16, &str), String> {
+    s.split(':')
+        .zip(s.split(':').skip(1))
+        .next()
+        .ok_or("Missing address and file name".to_string())
+        .and_then(|(s1,s2)| u16::from_str_radix(s1,16)
+                                    .map(|a| (a,s2))
+                                    .or(Err("Wrong loading address".to_string())))
+        .and_then(|(s1,s2)| if !s2.is_empty() { Ok((s1,s2)) } else { Err("Missing file name".to_string()) })
 }
 
-impl MemoryArrayMess {
-    pub fn new(size: usize) -> Result<MemoryArrayMess, &'static str> {
-        if size > 65536 {
-            Err("Too much!")
-        } else {
-            Ok(MemoryArrayMess {
-                   m: vec![0u8; size],
-                   fire_irq: false,
-                   fire_nmi: false,
-                   keyboard: None,
-                   printer: None,
-                   irq_generator: None,
-               })
-        }
+// into RAM only: a byte nobody would keep is an error, like a missing file
+fn load_binary(mem: &mut Bus, name: &str, address: u16) -> std::io::Result<()> {
+    use std::io::{Error, ErrorKind};
+    let blob = std::fs::read(name)?;
+    if address as usize + blob.len() > 0x10000 {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("{} bytes at {:04x} go past the top of memory", blob.len(), address)));
     }
-}
-
-impl Memory for MemoryArrayMess {
-    fn read(&mut self, a: usize) -> u8 {
-        if self.keyboard.is_some() && self.keyboard.unwrap() == a as u16 {
-            let tmp = self.m[a];
-            self.m[a] = 0x00;
-            tmp
-        } else {
-            self.m[a]
-        }
+    if let Some(a) = (0..blob.len()).map(|i| address + i as u16).find(|&a| !mem.is_ram(a)) {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("no RAM at {:04x}", a)));
     }
-    // FIXME. probably a would be a fine u16, instead of usize. check trait
-    fn write(&mut self, a: usize, v: u8) {
-        if self.printer.is_some() && self.printer.unwrap() == a as u16 {
-            if v == 0x7f {
-                print!("\x08"); // hack for backspace in raw mode
-            } else {
-                print!("{}", v as char); // cheap term.
-            }
-        } else if self.irq_generator.is_some() && self.irq_generator.unwrap() == a as u16 {
-            if v & 0x01 != 0 {
-                // bit 0 è /IRQ
-                self.fire_irq = false;
-            } else {
-                self.fire_irq = true;
-            }
-        } else {
-            self.m[a] = v;
-        }
+    for (i, &v) in blob.iter().enumerate() {
+        mem.poke(address + i as u16, v);
     }
+    Ok(())
 }
+
+
 // to be called only in T1 , to have meaningful information
-// todo: use a side effect free version of mem.read
 pub fn status_string<M: Memory>(pr: &P65, mem: &mut M) -> String {
     use disasm;
     let op = pr.op;
-    let param = (mem.read(pr.pc.wrapping_sub(1) as usize) as u16) |
-                ((mem.read(pr.pc as usize) as u16) << 8);
+    let param = (mem.peek(pr.pc.wrapping_sub(1)) as u16) |
+                ((mem.peek(pr.pc) as u16) << 8);
     let mut status = String::new();
     write!(&mut status,
            "{:3} {:7} {:?}",